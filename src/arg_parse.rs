@@ -1,9 +1,112 @@
-pub type Command = (String, Vec<String>);
-pub type CommandList = Vec<Command>;
+use crate::ast::{
+    ArithmeticCommand, ArithmeticForLoop, CaseClause, CaseItem, CaseTerminator, Command, CommandList, ForLoop,
+    FunctionDefinition, Group, IfClause, ListOperator, ParameterExpansion, ParameterOp, Pipeline, Redirect, RedirectOp,
+    SimpleCommand, Span, Subshell, WhileLoop, Word, WordPart,
+};
+use crate::shell::is_valid_name;
+use anyhow::Result;
+use std::collections::BTreeMap;
+
+/// Error for input that ends before the command is complete, e.g. inside a
+/// here-document. More input may turn it into a valid command.
+#[derive(Debug, thiserror::Error)]
+#[error("{0}")]
+pub struct IncompleteInput(String);
+
+#[derive(Debug, thiserror::Error)]
+pub enum ParseError {
+    /// The input contains no command, only blanks or comments.
+    #[error("input is empty")]
+    EmptyInput,
+    /// Syntax error at a span of the input.
+    #[error("{message}")]
+    Syntax { message: String, span: Span },
+}
+
+impl ParseError {
+    fn new(message: impl Into<String>, span: Span) -> Self {
+        Self::Syntax {
+            message: message.into(),
+            span,
+        }
+    }
+
+    /// Renders the message, the line of `input` where the error starts and a
+    /// caret underline of the span. For input of several lines the message
+    /// names the line.
+    pub fn render(&self, input: &str) -> String {
+        let ParseError::Syntax { message, span } = self else {
+            return self.to_string();
+        };
+        let start = span.start.min(input.len());
+        let line_start = input[..start].rfind('\n').map_or(0, |pos| pos + 1);
+        let line_end = input[start..].find('\n').map_or(input.len(), |pos| start + pos);
+        let end = span.end.clamp(start, line_end);
+
+        let mut message = message.clone();
+        if input.trim_end_matches('\n').contains('\n') {
+            let line_number = input[..start].matches('\n').count() + 1;
+            message = format!("line {line_number}: {message}");
+        }
+        let indent = " ".repeat(input[line_start..start].chars().count());
+        let underline = "^".repeat(input[start..end].chars().count().max(1));
+        format!("{message}\n{}\n{indent}{underline}", &input[line_start..line_end])
+    }
+}
+
+/// Words that have a special meaning as the first word of a command.
+pub const RESERVED_WORDS: [&str; 16] = [
+    "case", "do", "done", "elif", "else", "esac", "fi", "for", "function", "if", "in", "then", "until", "while", "{",
+    "}",
+];
+
+/// Returns the reserved word a word consists of, if it is an unquoted one.
+fn reserved_word(word: &Word) -> Option<&'static str> {
+    match word.parts.as_slice() {
+        [WordPart::Literal(text)] => RESERVED_WORDS.iter().find(|reserved| *reserved == text).copied(),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TokenKind {
+    Word(Word),
+    Operator(&'static str),
+    Redirect(i32, RedirectOp),
+    Newline,
+    Eof,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Token {
+    kind: TokenKind,
+    span: Span,
+}
 
 pub struct ArgParser {
     pos: usize,
     chars: Vec<char>,
+    offsets: Vec<usize>,
+    /// Position after the last here-document body read for the current line.
+    /// The next newline token continues from there.
+    here_doc_end: Option<usize>,
+    /// Number of enclosing `$(...)` command substitutions.
+    depth: usize,
+    /// Whether an unquoted `#` at the start of a word begins a comment.
+    comments: bool,
+    /// Whether extended patterns like `@(a|b)` are part of words.
+    extglob: bool,
+    /// Reserved words and operators that end the lists of the compound
+    /// commands being parsed, innermost last.
+    list_ends: Vec<&'static [&'static str]>,
+    /// Aliases that are expanded in command position.
+    aliases: BTreeMap<String, String>,
+    /// Aliases whose text is being parsed, with the position where the text
+    /// ends. They are not expanded again within their own text.
+    expanding: Vec<(String, usize)>,
+    /// Position from which on the next word is checked for an alias, as the
+    /// text of the last expanded alias ends with a blank.
+    alias_check_at: Option<usize>,
 }
 
 impl ArgParser {
@@ -12,161 +115,1445 @@ impl ArgParser {
         Self {
             pos: 0,
             chars: vec![],
+            offsets: vec![0],
+            here_doc_end: None,
+            depth: 0,
+            comments: true,
+            extglob: false,
+            list_ends: vec![],
+            aliases: BTreeMap::new(),
+            expanding: vec![],
+            alias_check_at: None,
+        }
+    }
+
+    /// Enables or disables comments, which are enabled by default.
+    pub fn comments(mut self, enabled: bool) -> Self {
+        self.comments = enabled;
+        self
+    }
+
+    /// Enables or disables extended patterns, which are disabled by default.
+    pub fn extglob(mut self, enabled: bool) -> Self {
+        self.extglob = enabled;
+        self
+    }
+
+    /// Sets the aliases that are expanded, by default there are none.
+    pub fn aliases(mut self, aliases: BTreeMap<String, String>) -> Self {
+        self.aliases = aliases;
+        self
+    }
+
+    pub fn parse_args(&mut self, input: &str) -> Result<CommandList> {
+        self.load(input);
+
+        self.skip_newlines()?;
+        if self.peek_token()?.kind == TokenKind::Eof {
+            return Err(ParseError::EmptyInput.into());
         }
+
+        self.parse_list()
     }
 
-    pub fn parse_args(&mut self, input: &str) -> anyhow::Result<CommandList> {
+    fn load(&mut self, input: &str) {
         self.pos = 0;
+        self.here_doc_end = None;
+        self.depth = 0;
+        self.list_ends.clear();
+        self.expanding.clear();
+        self.alias_check_at = None;
         self.chars = input.chars().collect();
+        self.offsets = input
+            .char_indices()
+            .map(|(offset, _)| offset)
+            .chain([input.len()])
+            .collect();
+    }
+
+    fn parse_list(&mut self) -> Result<CommandList> {
+        let mut items = vec![];
+        let mut operator = ListOperator::Seq;
+        self.skip_newlines()?;
+
+        loop {
+            items.push((operator, self.parse_pipeline()?));
 
-        if self.chars.is_empty() {
-            return Err(anyhow::anyhow!("input is empty"));
+            if self.at_list_end()? {
+                break;
+            }
+            let token = self.next_token()?;
+            operator = match token.kind {
+                TokenKind::Operator("&&") => ListOperator::And,
+                TokenKind::Operator("||") => ListOperator::Or,
+                TokenKind::Operator(";") | TokenKind::Newline => ListOperator::Seq,
+                _ => return Err(Self::unexpected_token(&token)),
+            };
+            self.skip_newlines()?;
+            if operator == ListOperator::Seq && self.at_list_end()? {
+                break;
+            }
         }
 
-        let mut ret = vec![];
-        let mut parts: Vec<String> = vec![];
-        let mut part = String::new();
+        let span = items[0].1.span.to(items[items.len() - 1].1.span);
+        Ok(CommandList { items, span })
+    }
+
+    /// Returns whether the next token ends the current command list: the end
+    /// of input, the `)` closing a command substitution or a reserved word
+    /// or operator that ends the list of a compound command.
+    fn at_list_end(&mut self) -> Result<bool> {
+        Ok(match self.peek_token()?.kind {
+            TokenKind::Eof => true,
+            TokenKind::Operator(")") if self.depth > 0 => true,
+            TokenKind::Operator(op) => self.is_list_end(op),
+            TokenKind::Word(word) => reserved_word(&word).is_some_and(|reserved| self.is_list_end(reserved)),
+            _ => false,
+        })
+    }
 
-        while !self.is_done() {
-            let cnt_wspace = self.skip_whitespaces();
+    fn is_list_end(&self, text: &str) -> bool {
+        self.list_ends.last().is_some_and(|ends| ends.contains(&text))
+    }
+
+    /// Parses the list of a compound command, which ends before one of the
+    /// reserved words or operators in `ends`.
+    fn parse_compound_list(&mut self, ends: &'static [&'static str], optional: bool) -> Result<CommandList> {
+        self.list_ends.push(ends);
+        let list = if optional { self.parse_optional_list() } else { self.parse_list() };
+        self.list_ends.pop();
+        list
+    }
+
+    /// Parses a command list that may be empty, like the commands of a
+    /// command substitution.
+    fn parse_optional_list(&mut self) -> Result<CommandList> {
+        self.skip_newlines()?;
+        if self.at_list_end()? {
+            let span = self.span_from(self.pos);
+            return Ok(CommandList { items: vec![], span });
+        }
+        self.parse_list()
+    }
+
+    fn parse_pipeline(&mut self) -> Result<Pipeline> {
+        let mut commands = vec![self.parse_command()?];
+
+        loop {
+            let token = self.peek_token()?;
+            match token.kind {
+                TokenKind::Operator("|") => {}
+                TokenKind::Operator("|&") => {
+                    // `|&` is a shorthand for `2>&1 |`
+                    let command = commands.last_mut().unwrap();
+                    command.redirects_mut().push(Redirect {
+                        fd: 2,
+                        op: RedirectOp::DupOutput,
+                        target: Word {
+                            parts: vec![WordPart::Literal("1".to_string())],
+                            span: token.span,
+                        },
+                        span: token.span,
+                    });
+                }
+                _ => break,
+            }
+            self.next_token()?;
+            self.skip_newlines()?;
+            commands.push(self.parse_command()?);
+        }
+
+        let span = commands[0].span().to(commands[commands.len() - 1].span());
+        Ok(Pipeline { commands, span })
+    }
+
+    fn parse_command(&mut self) -> Result<Command> {
+        self.expand_aliases()?;
+        if let Some(command) = self.parse_compound_command()? {
+            return Ok(command);
+        }
+        let token = self.peek_token()?;
+        if let TokenKind::Word(word) = &token.kind {
+            match reserved_word(word) {
+                Some("function") => {
+                    self.next_token()?;
+                    let token = self.next_token()?;
+                    let TokenKind::Word(name) = token.kind else {
+                        return Err(Self::unexpected_token(&token));
+                    };
+                    if self.peek_token()?.kind == TokenKind::Operator("(") {
+                        self.next_token()?;
+                        self.expect_operator(")")?;
+                    }
+                    return self.parse_function_body(name, token.span);
+                }
+                Some("then" | "elif" | "else" | "fi" | "do" | "done" | "esac" | "}") => {
+                    return Err(Self::unexpected_token(&token))
+                }
+                _ => {}
+            }
+        }
+
+        let mut assignments = vec![];
+        let mut words = vec![];
+        let mut redirects = vec![];
+        let start = self.peek_token()?.span;
+        let mut span = start;
+
+        loop {
+            let token = self.peek_token()?;
+            match token.kind {
+                TokenKind::Word(word) => {
+                    let word_start = self.token_start();
+                    if self.alias_check_at.is_some_and(|pos| word_start >= pos) {
+                        self.alias_check_at = None;
+                        if self.expand_alias(&word)? {
+                            self.expand_aliases()?;
+                            continue;
+                        }
+                    }
+                    self.next_token()?;
+                    span = span.to(word.span);
+                    if words.is_empty() && word.assignment_name().is_some() {
+                        assignments.push(word);
+                        // The command name after assignments may be an alias
+                        self.alias_check_at = Some(self.pos);
+                    } else {
+                        words.push(word);
+                    }
+                }
+                TokenKind::Redirect(..) => {
+                    let redirect = self.parse_redirect()?;
+                    span = span.to(redirect.span);
+                    redirects.push(redirect);
+                }
+                _ => break,
+            }
+        }
+
+        if assignments.is_empty() && words.is_empty() && redirects.is_empty() {
+            return Err(Self::unexpected_token(&self.peek_token()?));
+        }
+        if assignments.is_empty()
+            && words.len() == 1
+            && redirects.is_empty()
+            && self.peek_token()?.kind == TokenKind::Operator("(")
+        {
+            self.next_token()?;
+            self.expect_operator(")")?;
+            return self.parse_function_body(words.remove(0), start);
+        }
+
+        Ok(Command::Simple(SimpleCommand {
+            assignments,
+            words,
+            redirects,
+            span,
+        }))
+    }
+
+    /// Expands the alias the next word names and then the one the first word
+    /// of its text names, until the word names none.
+    fn expand_aliases(&mut self) -> Result<()> {
+        while let TokenKind::Word(word) = self.peek_token()?.kind {
+            if !self.expand_alias(&word)? {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces the next token, the unquoted `word`, by the text of the alias
+    /// it names. Returns false if it names no alias or one that is already
+    /// being expanded.
+    fn expand_alias(&mut self, word: &Word) -> Result<bool> {
+        let [WordPart::Literal(name)] = word.parts.as_slice() else {
+            return Ok(false);
+        };
+        let Some(text) = self.aliases.get(name).map(|text| text.chars().collect::<Vec<_>>()) else {
+            return Ok(false);
+        };
+        let start = self.token_start();
+        self.expanding.retain(|(_, end)| *end > start);
+        if self.expanding.iter().any(|(active, _)| active == name) {
+            return Ok(false);
+        }
+
+        let ends_with_blank = text.last().is_some_and(|ch| *ch == ' ' || *ch == '\t');
+        self.next_token()?;
+        let end = self.pos;
+        // The characters of the text are attributed to the start of the word
+        let offset = self.offsets[start];
+        self.offsets.splice(start..end, std::iter::repeat(offset).take(text.len()));
+        let text_end = start + text.len();
+        self.chars.splice(start..end, text);
+
+        let shift = |pos: &mut usize| {
+            if *pos >= end {
+                *pos = *pos - end + text_end;
+            }
+        };
+        self.expanding.iter_mut().for_each(|(_, pos)| shift(pos));
+        self.here_doc_end.iter_mut().for_each(shift);
+        self.alias_check_at.iter_mut().for_each(shift);
+        self.expanding.push((name.clone(), text_end));
+        if ends_with_blank {
+            self.alias_check_at = Some(text_end);
+        }
+        self.pos = start;
+        Ok(true)
+    }
+
+    /// Returns the position at which the next token starts.
+    fn token_start(&mut self) -> usize {
+        let pos = self.pos;
+        self.skip_whitespaces();
+        let start = self.pos;
+        self.pos = pos;
+        start
+    }
+
+    /// Parses a compound command if one starts with the next token.
+    fn parse_compound_command(&mut self) -> Result<Option<Command>> {
+        let token = self.peek_token()?;
+        let command = match &token.kind {
+            TokenKind::Operator("((") => self.parse_arithmetic_command(token.span)?,
+            TokenKind::Operator("(") => {
+                self.next_token()?;
+                self.parse_subshell(token.span)?
+            }
+            TokenKind::Word(word) => match reserved_word(word) {
+                Some("{") => self.parse_group()?,
+                Some("if") => self.parse_if_clause()?,
+                Some("case") => self.parse_case_clause()?,
+                Some(keyword @ ("while" | "until")) => self.parse_while_loop(keyword == "until")?,
+                Some("for") => self.parse_for_loop()?,
+                _ => return Ok(None),
+            },
+            _ => return Ok(None),
+        };
+        Ok(Some(command))
+    }
+
+    fn parse_redirect(&mut self) -> Result<Redirect> {
+        let token = self.next_token()?;
+        let TokenKind::Redirect(fd, op) = token.kind else {
+            return Err(Self::unexpected_token(&token));
+        };
+        let target_token = self.next_token()?;
+        let TokenKind::Word(mut target) = target_token.kind else {
+            return Err(Self::unexpected_token(&target_token));
+        };
+        let span = token.span.to(target.span);
+        if let RedirectOp::HereDoc { strip_tabs } = op {
+            target = self.read_here_document(&target, strip_tabs)?;
+        }
+
+        Ok(Redirect {
+            fd,
+            op,
+            target,
+            span,
+        })
+    }
+
+    /// Parses `((expression))`, which may be followed by redirections. If
+    /// the parentheses are not closed by `))`, they start nested subshells
+    /// like in `((cd dir); ls)`.
+    fn parse_arithmetic_command(&mut self, start: Span) -> Result<Command> {
+        self.next_token()?;
+        let inner_start = self.pos - 1;
+        let Some(expression) = self.scan_arithmetic_expression()? else {
+            self.pos = inner_start;
+            let span = Span::new(start.start, self.offsets[inner_start]);
+            return self.parse_subshell(span);
+        };
+        let mut span = start.to(self.span_from(self.pos));
+        let redirects = self.parse_trailing_redirects(&mut span)?;
+
+        Ok(Command::Arithmetic(ArithmeticCommand {
+            expression,
+            redirects,
+            span,
+        }))
+    }
+
+    /// Parses `( list )` after the opening parenthesis.
+    fn parse_subshell(&mut self, start: Span) -> Result<Command> {
+        let body = self.parse_compound_list(&[")"], false)?;
+        let end = self.expect_operator(")")?;
+        let mut span = start.to(end);
+        let redirects = self.parse_trailing_redirects(&mut span)?;
+
+        Ok(Command::Subshell(Subshell {
+            body,
+            redirects,
+            span,
+        }))
+    }
+
+    /// Parses `{ list; }`.
+    fn parse_group(&mut self) -> Result<Command> {
+        let start = self.next_token()?.span;
+        let body = self.parse_compound_list(&["}"], false)?;
+        let (_, end) = self.expect_reserved_word(&["}"])?;
+        let mut span = start.to(end);
+        let redirects = self.parse_trailing_redirects(&mut span)?;
+
+        Ok(Command::Group(Group {
+            body,
+            redirects,
+            span,
+        }))
+    }
+
+    /// Parses the redirections following a compound command and extends its
+    /// span over them.
+    fn parse_trailing_redirects(&mut self, span: &mut Span) -> Result<Vec<Redirect>> {
+        let mut redirects = vec![];
+        while let TokenKind::Redirect(..) = self.peek_token()?.kind {
+            let redirect = self.parse_redirect()?;
+            *span = span.to(redirect.span);
+            redirects.push(redirect);
+        }
+        Ok(redirects)
+    }
+
+    /// Consumes the next token, which has to be one of the `expected`
+    /// reserved words, and returns it with its span.
+    fn expect_reserved_word(&mut self, expected: &[&str]) -> Result<(&'static str, Span)> {
+        let token = self.next_token()?;
+        match &token.kind {
+            TokenKind::Word(word) => match reserved_word(word) {
+                Some(reserved) if expected.contains(&reserved) => Ok((reserved, token.span)),
+                _ => Err(Self::unexpected_token(&token)),
+            },
+            _ => Err(Self::unexpected_token(&token)),
+        }
+    }
+
+    fn parse_if_clause(&mut self) -> Result<Command> {
+        let start = self.next_token()?.span;
+        let mut branches = vec![];
+        let mut else_branch = None;
+        let end = loop {
+            let condition = self.parse_compound_list(&["then"], false)?;
+            self.expect_reserved_word(&["then"])?;
+            let body = self.parse_compound_list(&["elif", "else", "fi"], false)?;
+            branches.push((condition, body));
+
+            match self.expect_reserved_word(&["elif", "else", "fi"])? {
+                ("elif", _) => continue,
+                ("else", _) => {
+                    else_branch = Some(self.parse_compound_list(&["fi"], false)?);
+                    break self.expect_reserved_word(&["fi"])?.1;
+                }
+                (_, end) => break end,
+            }
+        };
+        let mut span = start.to(end);
+        let redirects = self.parse_trailing_redirects(&mut span)?;
+
+        Ok(Command::If(IfClause {
+            branches,
+            else_branch,
+            redirects,
+            span,
+        }))
+    }
 
-            let ch = match self.current_char() {
-                Some(ch) => ch,
-                None => break,
+    fn parse_case_clause(&mut self) -> Result<Command> {
+        let start = self.next_token()?.span;
+        let token = self.next_token()?;
+        let TokenKind::Word(word) = token.kind else {
+            return Err(Self::unexpected_token(&token));
+        };
+        self.skip_newlines()?;
+        self.expect_reserved_word(&["in"])?;
+
+        let mut items = vec![];
+        let end = loop {
+            self.skip_newlines()?;
+            let token = self.next_token()?;
+            let item_start = token.span;
+            let mut token = match &token.kind {
+                TokenKind::Word(word) if reserved_word(word) == Some("esac") => break token.span,
+                TokenKind::Operator("(") => self.next_token()?,
+                _ => token,
+            };
+
+            let mut patterns = vec![];
+            let mut item_end = loop {
+                let TokenKind::Word(pattern) = token.kind else {
+                    return Err(Self::unexpected_token(&token));
+                };
+                patterns.push(pattern);
+                let separator = self.next_token()?;
+                match separator.kind {
+                    TokenKind::Operator("|") => token = self.next_token()?,
+                    TokenKind::Operator(")") => break separator.span,
+                    _ => return Err(Self::unexpected_token(&separator)),
+                }
+            };
+
+            let body = self.parse_compound_list(&[";;", ";&", ";;&", "esac"], true)?;
+            if !body.items.is_empty() {
+                item_end = body.span;
+            }
+            let terminator = match self.peek_token()?.kind {
+                TokenKind::Operator(";;") => Some(CaseTerminator::Break),
+                TokenKind::Operator(";&") => Some(CaseTerminator::FallThrough),
+                TokenKind::Operator(";;&") => Some(CaseTerminator::Continue),
+                _ => None,
             };
+            if terminator.is_some() {
+                item_end = self.next_token()?.span;
+            }
+            items.push(CaseItem {
+                patterns,
+                body,
+                terminator: terminator.unwrap_or(CaseTerminator::Break),
+                span: item_start.to(item_end),
+            });
+        };
+        let mut span = start.to(end);
+        let redirects = self.parse_trailing_redirects(&mut span)?;
+
+        Ok(Command::Case(CaseClause {
+            word,
+            items,
+            redirects,
+            span,
+        }))
+    }
+
+    fn parse_while_loop(&mut self, until: bool) -> Result<Command> {
+        let start = self.next_token()?.span;
+        let condition = self.parse_compound_list(&["do"], false)?;
+        let (body, end) = self.parse_do_group()?;
+        let mut span = start.to(end);
+        let redirects = self.parse_trailing_redirects(&mut span)?;
+
+        Ok(Command::While(WhileLoop {
+            condition,
+            body,
+            until,
+            redirects,
+            span,
+        }))
+    }
+
+    fn parse_for_loop(&mut self) -> Result<Command> {
+        let start = self.next_token()?.span;
+        let token = self.next_token()?;
+        let name = match token.kind {
+            TokenKind::Operator("((") => return self.parse_arithmetic_for_loop(start),
+            TokenKind::Word(word) => word,
+            _ => return Err(Self::unexpected_token(&token)),
+        };
+        if !is_valid_name(&name.unquoted()) || name.parts.len() != 1 {
+            let message = format!("`{}': not a valid identifier", name.unquoted());
+            return Err(ParseError::new(message, name.span).into());
+        }
+
+        let mut words = None;
+        if self.peek_token()?.kind == TokenKind::Operator(";") {
+            self.next_token()?;
+        } else {
+            self.skip_newlines()?;
+            let token = self.peek_token()?;
+            if let TokenKind::Word(word) = &token.kind {
+                if reserved_word(word) == Some("in") {
+                    self.next_token()?;
+                    words = Some(self.parse_for_words()?);
+                }
+            }
+        }
+        self.skip_newlines()?;
+        let (body, end) = self.parse_do_group()?;
+        let mut span = start.to(end);
+        let redirects = self.parse_trailing_redirects(&mut span)?;
+
+        Ok(Command::For(ForLoop {
+            name: name.unquoted(),
+            words,
+            body,
+            redirects,
+            span,
+        }))
+    }
 
-            if ch == '|' {
+    /// Parses the words after `in` up to and including the `;` or newline
+    /// that ends them.
+    fn parse_for_words(&mut self) -> Result<Vec<Word>> {
+        let mut words = vec![];
+        loop {
+            let token = self.next_token()?;
+            match token.kind {
+                TokenKind::Word(word) => words.push(word),
+                TokenKind::Operator(";") | TokenKind::Newline => return Ok(words),
+                _ => return Err(Self::unexpected_token(&token)),
+            }
+        }
+    }
+
+    /// Parses `for ((init; condition; update))` after the `((` and the
+    /// following loop body.
+    fn parse_arithmetic_for_loop(&mut self, start: Span) -> Result<Command> {
+        let expr_start = self.pos;
+        if !self.skip_arithmetic_expression()? {
+            let span = start.to(self.span_from(self.pos));
+            return Err(ParseError::new("syntax error: `((' is not closed by `))'", span).into());
+        }
+        let expr_end = self.pos;
+        self.pos += 2;
+
+        let mut semicolons = vec![];
+        let mut depth = 0;
+        for pos in expr_start..expr_end {
+            match self.chars[pos] {
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                ';' if depth == 0 => semicolons.push(pos),
+                _ => {}
+            }
+        }
+        let [first, second] = semicolons.as_slice() else {
+            let span = start.to(self.span_from(self.pos));
+            return Err(ParseError::new("syntax error: arithmetic expression required", span).into());
+        };
+        let init = self.arithmetic_word(expr_start, *first)?;
+        let condition = self.arithmetic_word(first + 1, *second)?;
+        let update = self.arithmetic_word(second + 1, expr_end)?;
+
+        if self.peek_token()?.kind == TokenKind::Operator(";") {
+            self.next_token()?;
+        }
+        self.skip_newlines()?;
+        let (body, end) = self.parse_do_group()?;
+        let mut span = start.to(end);
+        let redirects = self.parse_trailing_redirects(&mut span)?;
+
+        Ok(Command::ArithmeticFor(ArithmeticForLoop {
+            init,
+            condition,
+            update,
+            body,
+            redirects,
+            span,
+        }))
+    }
+
+    /// Parses the body of a function definition whose name and parentheses
+    /// have been consumed already. The body is a compound command, usually
+    /// a group.
+    fn parse_function_body(&mut self, name: Word, start: Span) -> Result<Command> {
+        let is_valid = matches!(name.parts.as_slice(), [WordPart::Literal(_)]) && reserved_word(&name).is_none();
+        if !is_valid {
+            let message = format!("`{}': not a valid identifier", name.unquoted());
+            return Err(ParseError::new(message, name.span).into());
+        }
+        self.skip_newlines()?;
+        let Some(body) = self.parse_compound_command()? else {
+            return Err(Self::unexpected_token(&self.peek_token()?));
+        };
+
+        Ok(Command::FunctionDefinition(FunctionDefinition {
+            name: name.unquoted(),
+            span: start.to(body.span()),
+            body: Box::new(body),
+        }))
+    }
+
+    /// Consumes the next token, which has to be the operator `expected`,
+    /// and returns its span.
+    fn expect_operator(&mut self, expected: &str) -> Result<Span> {
+        let token = self.next_token()?;
+        match token.kind {
+            TokenKind::Operator(op) if op == expected => Ok(token.span),
+            _ => Err(Self::unexpected_token(&token)),
+        }
+    }
+
+    /// Parses `do list; done` and returns the list with the span of `done`.
+    fn parse_do_group(&mut self) -> Result<(CommandList, Span)> {
+        self.expect_reserved_word(&["do"])?;
+        let body = self.parse_compound_list(&["done"], false)?;
+        let (_, end) = self.expect_reserved_word(&["done"])?;
+        Ok((body, end))
+    }
+
+    fn skip_newlines(&mut self) -> Result<()> {
+        while self.peek_token()?.kind == TokenKind::Newline {
+            self.next_token()?;
+        }
+        Ok(())
+    }
+
+    /// Reads the body of a here-document, which starts on the line following
+    /// the current one or after the body of a previous here-document.
+    fn read_here_document(&mut self, delimiter: &Word, strip_tabs: bool) -> Result<Word> {
+        let start = match self.here_doc_end {
+            Some(end) => end,
+            None => self.find_next_line()?,
+        };
+        let quoted = delimiter
+            .parts
+            .iter()
+            .any(|part| !matches!(part, WordPart::Literal(_) | WordPart::Parameter(_)));
+        let delimiter = delimiter.unquoted();
+
+        let mut body = String::new();
+        let mut line_start = start;
+        loop {
+            if line_start >= self.chars.len() {
+                return Err(IncompleteInput(format!(
+                    "here-document delimited by end-of-file (wanted `{delimiter}')"
+                ))
+                .into());
+            }
+            let line_end = self.chars[line_start..]
+                .iter()
+                .position(|ch| *ch == '\n')
+                .map_or(self.chars.len(), |len| line_start + len);
+            let mut line: String = self.chars[line_start..line_end].iter().collect();
+            if strip_tabs {
+                line = line.trim_start_matches('\t').to_string();
+            }
+            let next_line_start = (line_end + 1).min(self.chars.len());
+            if line == delimiter {
+                self.here_doc_end = Some(next_line_start);
+                break;
+            }
+            body.push_str(&line);
+            body.push('\n');
+            line_start = next_line_start;
+        }
+
+        let span = Span::new(self.offsets[start], self.offsets[line_start]);
+        let parts = if quoted {
+            vec![WordPart::SingleQuoted(body)]
+        } else {
+            vec![WordPart::DoubleQuoted(self.scan_here_document_body(&body, span.start)?)]
+        };
+        Ok(Word { parts, span })
+    }
+
+    /// Returns the position after the newline that ends the current line.
+    fn find_next_line(&mut self) -> Result<usize> {
+        let pos = self.pos;
+        let line_end = loop {
+            match self.next_token()?.kind {
+                TokenKind::Newline => break Ok(self.pos),
+                TokenKind::Eof => {
+                    break Err(IncompleteInput("here-document without body".to_string()))
+                }
+                _ => {}
+            }
+        };
+        self.pos = pos;
+        Ok(line_end?)
+    }
+
+    /// Scans the body of a here-document whose delimiter is unquoted. Quotes
+    /// have no special meaning in there, but expansions and escapes have.
+    fn scan_here_document_body(&self, body: &str, offset: usize) -> Result<Vec<WordPart>> {
+        self.nested_parser(body, offset).scan_quoted_parts(|_| false, true)
+    }
+
+    /// Returns a parser for a part of the input that starts at the byte
+    /// `offset`, so that spans refer to the whole input.
+    fn nested_parser(&self, source: &str, offset: usize) -> ArgParser {
+        let mut parser = ArgParser::new()
+            .comments(self.comments)
+            .extglob(self.extglob)
+            .aliases(self.aliases.clone());
+        parser.load(source);
+        parser.offsets.iter_mut().for_each(|pos| *pos += offset);
+        parser
+    }
+
+    fn unexpected_token(token: &Token) -> anyhow::Error {
+        let text = match &token.kind {
+            TokenKind::Eof => {
+                return IncompleteInput("syntax error: unexpected end of input".to_string()).into()
+            }
+            TokenKind::Newline => "newline".to_string(),
+            TokenKind::Operator(op) => op.to_string(),
+            TokenKind::Redirect(_, op) => op.as_str().to_string(),
+            TokenKind::Word(word) => word.unquoted(),
+        };
+        ParseError::new(format!("syntax error near unexpected token `{text}'"), token.span).into()
+    }
+
+    fn peek_token(&mut self) -> Result<Token> {
+        let (pos, here_doc_end) = (self.pos, self.here_doc_end);
+        let token = self.next_token();
+        (self.pos, self.here_doc_end) = (pos, here_doc_end);
+        token
+    }
+
+    fn next_token(&mut self) -> Result<Token> {
+        self.skip_whitespaces();
+        if self.comments && self.current_char() == Some('#') {
+            while self.current_char().is_some_and(|ch| ch != '\n') {
                 self.pos += 1;
-                if !part.is_empty() {
-                    parts.push(part);
-                    part = String::new();
+            }
+        }
+        let start = self.pos;
+
+        let kind = match self.scan_redirect_operator() {
+            Some((fd, op)) => TokenKind::Redirect(fd, op),
+            None => match self.current_char() {
+                None => TokenKind::Eof,
+                Some('\n') => {
+                    self.pos += 1;
+                    let span = self.span_from(start);
+                    if let Some(end) = self.here_doc_end.take() {
+                        self.pos = end;
+                    }
+                    return Ok(Token {
+                        kind: TokenKind::Newline,
+                        span,
+                    });
                 }
-                if parts.is_empty() {
-                    return Err(anyhow::anyhow!("pipe without command"));
+                Some(ch) if Self::is_operator_char(ch) => {
+                    TokenKind::Operator(self.scan_operator()?)
                 }
-                ret.push(Self::get_command(parts));
-                parts = vec![];
+                Some(_) => TokenKind::Word(self.scan_word()?),
+            },
+        };
+
+        Ok(Token {
+            kind,
+            span: self.span_from(start),
+        })
+    }
+
+    fn span_from(&self, start: usize) -> Span {
+        Span::new(self.offsets[start], self.offsets[self.pos])
+    }
+
+    fn is_operator_char(ch: char) -> bool {
+        matches!(ch, '|' | '&' | ';' | '(' | ')')
+    }
+
+    fn is_word_delimiter(ch: char) -> bool {
+        ch.is_whitespace() || Self::is_operator_char(ch) || ch == '>' || ch == '<'
+    }
+
+    /// Scans a redirection operator that may be preceded by a file descriptor
+    /// number (e.g. `>`, `2>>`, `3<`, `2>&`). Digits only count as descriptor
+    /// if the operator follows them immediately, otherwise they start a word.
+    fn scan_redirect_operator(&mut self) -> Option<(i32, RedirectOp)> {
+        if self.current_char() == Some('&') && self.chars.get(self.pos + 1) == Some(&'>') {
+            return if self.chars.get(self.pos + 2) == Some(&'>') {
+                self.pos += 3;
+                Some((1, RedirectOp::AppendAll))
+            } else {
+                self.pos += 2;
+                Some((1, RedirectOp::OutputAll))
+            };
+        }
+
+        let start = self.pos;
+        let mut end = start;
+        while end < self.chars.len() && self.chars[end].is_ascii_digit() {
+            end += 1;
+        }
+        let first = *self.chars.get(end)?;
+        if first != '>' && first != '<' {
+            return None;
+        }
+        let second = self.chars.get(end + 1).copied();
+
+        let (op, len) = match (first, second) {
+            ('>', Some('>')) => (RedirectOp::Append, 2),
+            ('>', Some('&')) => (RedirectOp::DupOutput, 2),
+            ('>', _) => (RedirectOp::Output, 1),
+            ('<', Some('<')) => match self.chars.get(end + 2) {
+                Some('<') => (RedirectOp::HereString, 3),
+                Some('-') => (RedirectOp::HereDoc { strip_tabs: true }, 3),
+                _ => (RedirectOp::HereDoc { strip_tabs: false }, 2),
+            },
+            ('<', Some('>')) => (RedirectOp::ReadWrite, 2),
+            ('<', Some('&')) => (RedirectOp::DupInput, 2),
+            _ => (RedirectOp::Input, 1),
+        };
+
+        let fd = if end > start {
+            let digits: String = self.chars[start..end].iter().collect();
+            digits.parse().ok()?
+        } else {
+            op.default_fd()
+        };
+
+        self.pos = end + len;
+        Some((fd, op))
+    }
+
+    fn scan_operator(&mut self) -> Result<&'static str> {
+        let start = self.pos;
+        let ch = self.current_char().unwrap();
+        self.pos += 1;
+        match ch {
+            '(' if self.current_char() == Some('(') => {
+                self.pos += 1;
+                return Ok("((");
+            }
+            '(' => return Ok("("),
+            ')' => return Ok(")"),
+            _ => {}
+        }
+        if ch == '|' && self.current_char() == Some('&') {
+            self.pos += 1;
+            return Ok("|&");
+        }
+        let doubled = self.current_char() == Some(ch);
+        let op = match (ch, doubled) {
+            ('|', true) => "||",
+            ('|', false) => "|",
+            ('&', true) => "&&",
+            (';', true) if self.chars.get(self.pos + 1) == Some(&'&') => {
+                self.pos += 2;
+                return Ok(";;&");
+            }
+            (';', true) => ";;",
+            (';', false) if self.current_char() == Some('&') => {
+                self.pos += 1;
+                return Ok(";&");
+            }
+            (';', false) => ";",
+            _ => {
+                let span = self.span_from(start);
+                return Err(ParseError::new("background execution with `&' is not supported", span).into());
+            }
+        };
+        if doubled {
+            self.pos += 1;
+        }
+        Ok(op)
+    }
+
+    fn scan_word(&mut self) -> Result<Word> {
+        let start = self.pos;
+        let parts = self.scan_word_parts(Self::is_word_delimiter)?;
+
+        Ok(Word {
+            parts,
+            span: self.span_from(start),
+        })
+    }
+
+    /// Scans unquoted text, quoted strings and expansions up to the first
+    /// unquoted character that satisfies `is_end`.
+    fn scan_word_parts(&mut self, is_end: fn(char) -> bool) -> Result<Vec<WordPart>> {
+        let mut parts = vec![];
+
+        while let Some(ch) = self.current_char() {
+            if is_end(ch) {
+                break;
+            }
+            match ch {
+                '\'' => parts.push(WordPart::SingleQuoted(self.scan_single_quoted_string()?)),
+                '"' => parts.push(WordPart::DoubleQuoted(self.scan_double_quoted_string()?)),
+                _ => self.scan_string(&mut parts, is_end)?,
+            }
+        }
+
+        Ok(parts)
+    }
+
+    fn is_done(&self) -> bool {
+        self.pos >= self.chars.len()
+    }
+
+    fn skip_whitespaces(&mut self) -> usize {
+        let mut count = 0;
+        while !self.is_done() {
+            let ch = self.current_char().unwrap();
+            if ch.is_whitespace() && ch != '\n' {
+                self.pos += 1;
+                count += 1;
+            } else if ch == '\\' && self.chars.get(self.pos + 1) == Some(&'\n') {
+                self.pos += 2;
+                count += 2;
+            } else {
+                break;
+            }
+        }
+        count
+    }
+
+    fn scan_string(&mut self, parts: &mut Vec<WordPart>, is_end: fn(char) -> bool) -> Result<()> {
+        let mut literal = String::new();
+
+        while !self.is_done() {
+            let ch = self.current_char().unwrap();
+            if is_end(ch) || ch == '\'' || ch == '"' {
+                break;
+            }
+            if ch == '$' {
+                match self.scan_dollar(false)? {
+                    Some(part) => {
+                        Self::push_literal(parts, &mut literal);
+                        parts.push(part);
+                    }
+                    None => literal.push(ch),
+                }
+                continue;
+            }
+            if ch == '`' {
+                Self::push_literal(parts, &mut literal);
+                parts.push(self.scan_backquoted(false)?);
+                continue;
+            }
+            if let Some(pattern) = self.scan_extended_pattern() {
+                literal.push_str(&pattern);
                 continue;
             }
-
-            let next_str = match ch {
-                '\'' => self.scan_single_quoted_string(),
-                '"' => self.scan_double_quoted_string(),
-                _ => self.scan_string(),
-            };
-
-            if !part.is_empty() {
-                if cnt_wspace > 0 {
-                    parts.push(part);
-                    part = next_str;
+            self.pos += 1;
+            if ch == '\\' {
+                match self.current_char() {
+                    // A backslash-newline continues the line
+                    Some('\n') => self.pos += 1,
+                    Some(escaped) => {
+                        Self::push_literal(parts, &mut literal);
+                        parts.push(WordPart::Escaped(escaped));
+                        self.pos += 1;
+                    }
+                    None => return Err(IncompleteInput("line continues after `\\'".to_string()).into()),
+                }
+            } else {
+                literal.push(ch);
+            }
+        }
+
+        Self::push_literal(parts, &mut literal);
+        Ok(())
+    }
+
+    /// Scans an extended pattern like `@(a|b)`, whose parentheses and `|`
+    /// belong to the word. Returns `None` if there is none at the current
+    /// position.
+    fn scan_extended_pattern(&mut self) -> Option<String> {
+        if !self.extglob
+            || !matches!(self.current_char()?, '?' | '*' | '+' | '@' | '!')
+            || self.chars.get(self.pos + 1) != Some(&'(')
+        {
+            return None;
+        }
+        let mut end = self.pos + 2;
+        let mut depth = 0;
+        loop {
+            match self.chars.get(end)? {
+                '\\' => end += 1,
+                '(' => depth += 1,
+                ')' if depth == 0 => break,
+                ')' => depth -= 1,
+                '\n' => return None,
+                _ => {}
+            }
+            end += 1;
+        }
+        let pattern = self.chars[self.pos..=end].iter().collect();
+        self.pos = end + 1;
+        Some(pattern)
+    }
+
+    fn push_literal(parts: &mut Vec<WordPart>, literal: &mut String) {
+        if !literal.is_empty() {
+            parts.push(WordPart::Literal(std::mem::take(literal)));
+        }
+    }
+
+    fn scan_single_quoted_string(&mut self) -> Result<String> {
+        let mut ret = String::new();
+        self.pos += 1;
+        loop {
+            match self.current_char() {
+                None => return Err(Self::missing_closing_quote('\'')),
+                Some('\'') => break,
+                Some(ch) => ret.push(ch),
+            }
+            self.pos += 1;
+        }
+        self.pos += 1;
+
+        Ok(ret)
+    }
+
+    fn scan_double_quoted_string(&mut self) -> Result<Vec<WordPart>> {
+        self.pos += 1;
+        let parts = self.scan_quoted_parts(|ch| ch == '"', false)?;
+        if self.current_char() != Some('"') {
+            return Err(Self::missing_closing_quote('"'));
+        }
+        self.pos += 1;
+
+        Ok(parts)
+    }
+
+    fn missing_closing_quote(quote: char) -> anyhow::Error {
+        IncompleteInput(format!("unexpected end of input while looking for matching `{quote}'")).into()
+    }
+
+    /// Scans text following the rules for double quotes up to the first
+    /// character that satisfies `is_end` or the end of input. In
+    /// here-documents `\"` is no escape sequence.
+    fn scan_quoted_parts(&mut self, is_end: fn(char) -> bool, here_doc: bool) -> Result<Vec<WordPart>> {
+        let mut parts = vec![];
+        let mut literal = String::new();
+
+        while let Some(mut ch) = self.current_char() {
+            if is_end(ch) {
+                break;
+            }
+            if ch == '$' {
+                match self.scan_dollar(true)? {
+                    Some(part) => {
+                        Self::push_literal(&mut parts, &mut literal);
+                        parts.push(part);
+                    }
+                    None => literal.push(ch),
+                }
+                continue;
+            }
+            if ch == '`' {
+                Self::push_literal(&mut parts, &mut literal);
+                parts.push(self.scan_backquoted(!here_doc)?);
+                continue;
+            }
+            self.pos += 1;
+            if ch == '\\' {
+                match self.current_char() {
+                    Some('\n') => {
+                        self.pos += 1;
+                        continue;
+                    }
+                    Some(next @ ('\\' | '$' | '`')) => {
+                        ch = next;
+                        self.pos += 1;
+                    }
+                    Some('"') if !here_doc => {
+                        ch = '"';
+                        self.pos += 1;
+                    }
+                    _ => {}
+                }
+            }
+            literal.push(ch);
+        }
+
+        Self::push_literal(&mut parts, &mut literal);
+        Ok(parts)
+    }
+
+    /// Scans an expansion introduced by `$`. If the dollar sign does not
+    /// start an expansion it is consumed and `None` is returned, so that the
+    /// caller takes it literally.
+    fn scan_dollar(&mut self, quoted: bool) -> Result<Option<WordPart>> {
+        self.pos += 1;
+        let parameter = match self.current_char() {
+            Some('(') => {
+                self.pos += 1;
+                if self.current_char() == Some('(') {
+                    let start = self.pos;
+                    self.pos += 1;
+                    if let Some(expression) = self.scan_arithmetic_expression()? {
+                        return Ok(Some(WordPart::Arithmetic(expression)));
+                    }
+                    // Something like `$( (cmd) )` is a command substitution
+                    self.pos = start;
+                }
+                return Ok(Some(self.scan_command_substitution()?));
+            }
+            Some('{') => {
+                self.pos += 1;
+                self.scan_braced_parameter(quoted)?
+            }
+            _ => {
+                let name = self.scan_parameter_name(false);
+                if name.is_empty() {
+                    return Ok(None);
+                }
+                ParameterExpansion {
+                    name,
+                    op: ParameterOp::Value,
+                }
+            }
+        };
+
+        Ok(Some(WordPart::Parameter(parameter)))
+    }
+
+    /// Scans the expression of `$((...))` or `((...))` after the opening
+    /// parentheses up to and including the closing `))`. Returns `None` if
+    /// the parentheses are not closed by `))`. The expression is subject to
+    /// the same expansions as text in double quotes.
+    fn scan_arithmetic_expression(&mut self) -> Result<Option<Word>> {
+        let start = self.pos;
+        if !self.skip_arithmetic_expression()? {
+            return Ok(None);
+        }
+        let word = self.arithmetic_word(start, self.pos)?;
+        self.pos += 2;
+        Ok(Some(word))
+    }
+
+    /// Moves to the closing `))` of an arithmetic expression. Returns false
+    /// if a single `)` closes it instead.
+    fn skip_arithmetic_expression(&mut self) -> Result<bool> {
+        let mut depth = 0;
+        loop {
+            match self.current_char() {
+                None => {
+                    return Err(IncompleteInput(
+                        "unexpected end of input while looking for matching `))'".to_string(),
+                    )
+                    .into())
+                }
+                Some('(') => depth += 1,
+                Some(')') if depth > 0 => depth -= 1,
+                Some(')') if self.chars.get(self.pos + 1) == Some(&')') => return Ok(true),
+                Some(')') => return Ok(false),
+                _ => {}
+            }
+            self.pos += 1;
+        }
+    }
+
+    /// Returns the arithmetic expression between the character positions
+    /// `start` and `end` as a word.
+    fn arithmetic_word(&self, start: usize, end: usize) -> Result<Word> {
+        let source: String = self.chars[start..end].iter().collect();
+        let span = Span::new(self.offsets[start], self.offsets[end]);
+        let parts = self.nested_parser(&source, span.start).scan_quoted_parts(|_| false, false)?;
+        Ok(Word { parts, span })
+    }
+
+    /// Scans the commands of `$(...)` and the closing parenthesis.
+    fn scan_command_substitution(&mut self) -> Result<WordPart> {
+        self.depth += 1;
+        let commands = self.parse_compound_list(&[], true);
+        self.depth -= 1;
+        let commands = commands?;
+
+        let token = self.next_token()?;
+        match token.kind {
+            TokenKind::Operator(")") => Ok(WordPart::CommandSubstitution(commands)),
+            TokenKind::Eof => Err(IncompleteInput(
+                "unexpected end of input while looking for matching `)'".to_string(),
+            )
+            .into()),
+            _ => Err(Self::unexpected_token(&token)),
+        }
+    }
+
+    /// Scans a command substitution in the legacy form with backquotes. A
+    /// backslash only escapes `$`, `` ` ``, `\\` and, if the substitution is
+    /// inside double quotes, `"`.
+    fn scan_backquoted(&mut self, in_double_quotes: bool) -> Result<WordPart> {
+        self.pos += 1;
+        let start = self.pos;
+        let mut source = String::new();
+
+        loop {
+            match self.current_char() {
+                None => {
+                    return Err(IncompleteInput(
+                        "unexpected end of input while looking for matching ``'".to_string(),
+                    )
+                    .into())
+                }
+                Some('`') => break,
+                Some('\\') => {
+                    self.pos += 1;
+                    match self.current_char() {
+                        Some(ch @ ('$' | '`' | '\\')) => source.push(ch),
+                        Some('"') if in_double_quotes => source.push('"'),
+                        Some(ch) => {
+                            source.push('\\');
+                            source.push(ch);
+                        }
+                        None => continue,
+                    }
+                }
+                Some(ch) => source.push(ch),
+            }
+            self.pos += 1;
+        }
+        let offset = self.offsets[start];
+        let span = self.span_from(start);
+        self.pos += 1;
+
+        // The body is complete, so a missing end is a syntax error in there
+        let commands = self
+            .nested_parser(&source, offset)
+            .parse_optional_list()
+            .map_err(|err| match err.downcast::<IncompleteInput>() {
+                Ok(incomplete) => ParseError::new(incomplete.to_string(), span).into(),
+                Err(err) => err,
+            })?;
+        Ok(WordPart::CommandSubstitution(commands))
+    }
+
+    fn scan_braced_parameter(&mut self, quoted: bool) -> Result<ParameterExpansion> {
+        if self.current_char() == Some('#') && !matches!(self.chars.get(self.pos + 1), Some('}') | None) {
+            self.pos += 1;
+            let name = self.scan_parameter_name(true);
+            if name.is_empty() {
+                return Err(self.bad_substitution());
+            }
+            self.expect_closing_brace()?;
+            return Ok(ParameterExpansion { name, op: ParameterOp::Length });
+        }
+
+        let name = self.scan_parameter_name(true);
+        if name.is_empty() {
+            return Err(self.bad_substitution());
+        }
+        let op_char = self.current_char();
+        if op_char.is_some() {
+            self.pos += 1;
+        }
+        let op = match op_char {
+            Some('}') => return Ok(ParameterExpansion { name, op: ParameterOp::Value }),
+            Some(':') => match self.current_char() {
+                Some(op_char @ ('-' | '=' | '?' | '+')) => {
+                    self.pos += 1;
+                    self.scan_default_operation(op_char, true, quoted)?
+                }
+                _ => {
+                    let offset = self.scan_brace_word(|ch| ch == ':' || ch == '}', false)?;
+                    let length = match self.current_char() {
+                        Some(':') => {
+                            self.pos += 1;
+                            Some(self.scan_brace_word(|ch| ch == '}', false)?)
+                        }
+                        _ => None,
+                    };
+                    ParameterOp::Substring { offset, length }
+                }
+            },
+            Some(op_char @ ('-' | '=' | '?' | '+')) => {
+                self.scan_default_operation(op_char, false, quoted)?
+            }
+            Some(op_char @ ('#' | '%')) => {
+                let longest = self.skip_char(op_char);
+                let pattern = self.scan_brace_word(|ch| ch == '}', false)?;
+                if op_char == '#' {
+                    ParameterOp::RemovePrefix { pattern, longest }
                 } else {
-                    part.push_str(&next_str);
+                    ParameterOp::RemoveSuffix { pattern, longest }
                 }
-            } else {
-                part = next_str;
             }
-        }
-
-        if !part.is_empty() {
-            parts.push(part);
-        }
-
-        ret.push(Self::get_command(parts));
-
-        Ok(ret)
-    }
+            Some('/') => {
+                let all = self.skip_char('/');
+                let pattern = self.scan_brace_word(|ch| ch == '/' || ch == '}', false)?;
+                self.skip_char('/');
+                let replacement = self.scan_brace_word(|ch| ch == '}', quoted)?;
+                ParameterOp::Replace { pattern, replacement, all }
+            }
+            Some(op_char @ ('^' | ',')) => {
+                let all = self.skip_char(op_char);
+                let pattern = self.scan_brace_word(|ch| ch == '}', false)?;
+                ParameterOp::CaseModification { upper: op_char == '^', all, pattern }
+            }
+            None => return Err(Self::missing_closing_brace()),
+            _ => return Err(self.bad_substitution()),
+        };
+        self.expect_closing_brace()?;
 
-    fn get_command(parts: Vec<String>) -> Command {
-        let command = parts[0].clone();
-        let args = parts.into_iter().skip(1).collect::<Vec<String>>();
-        (command, args)
+        Ok(ParameterExpansion { name, op })
     }
 
-    fn is_done(&self) -> bool {
-        self.pos >= self.chars.len()
+    /// Scans the word of `${name-word}`, `${name=word}`, `${name?word}` or
+    /// `${name+word}` and their variants with a colon.
+    fn scan_default_operation(&mut self, op_char: char, check_null: bool, quoted: bool) -> Result<ParameterOp> {
+        let word = self.scan_brace_word(|ch| ch == '}', quoted)?;
+        Ok(match op_char {
+            '-' => ParameterOp::Default { word, check_null },
+            '=' => ParameterOp::Assign { word, check_null },
+            '?' => ParameterOp::Error { word, check_null },
+            _ => ParameterOp::Alternative { word, check_null },
+        })
     }
 
-    fn skip_whitespaces(&mut self) -> usize {
-        let mut count = 0;
-        while !self.is_done() {
-            if self.current_char().unwrap().is_whitespace() {
-                self.pos += 1;
-                count += 1;
-            } else {
-                break;
-            }
+    /// Scans a word within a parameter expansion up to the first unquoted
+    /// character that satisfies `is_end`. Inside of double quotes, the word
+    /// follows the rules for double quotes if `quoted` is set.
+    fn scan_brace_word(&mut self, is_end: fn(char) -> bool, quoted: bool) -> Result<Word> {
+        let start = self.pos;
+        let parts = if quoted {
+            self.scan_quoted_parts(is_end, false)?
+        } else {
+            self.scan_word_parts(is_end)?
+        };
+        if self.is_done() {
+            return Err(Self::missing_closing_brace());
         }
-        count
-    }
 
-    fn scan_string(&mut self) -> String {
-        let mut ret = String::new();
-        let mut escaped = false;
+        Ok(Word {
+            parts,
+            span: self.span_from(start),
+        })
+    }
 
-        while !self.is_done() {
-            let ch = self.current_char().unwrap();
-            if escaped {
-                ret.push(ch);
+    fn expect_closing_brace(&mut self) -> Result<()> {
+        match self.current_char() {
+            Some('}') => {
                 self.pos += 1;
-                escaped = false;
-                continue;
-            }
-            if ch.is_whitespace() || ch == '\'' || ch == '"' {
-                break;
+                Ok(())
             }
-            match ch {
-                '\\' => {
-                    escaped = true;
-                }
-                _ => {
-                    ret.push(ch);
-                }
-            }
-            self.pos += 1;
+            Some(_) => Err(self.bad_substitution()),
+            None => Err(Self::missing_closing_brace()),
         }
-
-        ret
     }
 
-    fn scan_single_quoted_string(&mut self) -> String {
-        let mut ret = String::new();
-        self.pos += 1;
-        while !self.is_done() {
-            let ch = self.current_char().unwrap();
+    /// Consumes the current character if it is `ch`.
+    fn skip_char(&mut self, ch: char) -> bool {
+        let found = self.current_char() == Some(ch);
+        if found {
             self.pos += 1;
-            if ch == '\'' {
-                break;
-            }
-            ret.push(ch);
         }
-
-        ret
+        found
     }
 
-    fn scan_double_quoted_string(&mut self) -> String {
-        let mut ret = String::new();
-        self.pos += 1;
-        while !self.is_done() {
-            let mut ch = self.current_char().unwrap();
-            self.pos += 1;
-            match ch {
-                '\\' => {
-                    let next_ch_opt = self.current_char();
-                    match next_ch_opt {
-                        Some('\\') | Some('"') | Some('$') => { // escaping
-                            ch = next_ch_opt.unwrap();
-                            self.pos += 1;
-                        }
-                        _ => {},
-                    }
+    /// Scans the name of a parameter: an identifier, the digits of a
+    /// positional parameter or a special parameter like `?`. Outside of
+    /// braces only a single digit belongs to the name.
+    fn scan_parameter_name(&mut self, braced: bool) -> String {
+        let start = self.pos;
+        match self.current_char() {
+            Some(ch) if ch.is_ascii_alphabetic() || ch == '_' => {
+                while self
+                    .current_char()
+                    .is_some_and(|ch| ch.is_ascii_alphanumeric() || ch == '_')
+                {
+                    self.pos += 1;
+                }
+            }
+            Some(ch) if ch.is_ascii_digit() => {
+                self.pos += 1;
+                while braced && self.current_char().is_some_and(|ch| ch.is_ascii_digit()) {
+                    self.pos += 1;
                 }
-                '"' => break,
-                _ => {}
             }
-            ret.push(ch);
+            Some('?' | '$' | '#' | '@' | '*' | '!' | '-') => self.pos += 1,
+            _ => {}
         }
+        self.chars[start..self.pos].iter().collect()
+    }
+
+    fn bad_substitution(&self) -> anyhow::Error {
+        let end = (self.pos + 1).min(self.chars.len());
+        let span = Span::new(self.offsets[self.pos], self.offsets[end]);
+        ParseError::new("bad substitution", span).into()
+    }
 
-        ret
+    fn missing_closing_brace() -> anyhow::Error {
+        IncompleteInput("unexpected end of input while looking for matching `}'".to_string()).into()
     }
 
     fn current_char(&self) -> Option<char> {
@@ -181,12 +1568,30 @@ impl ArgParser {
 mod tests {
     use super::*;
 
+    fn simple_command(command: &Command) -> &SimpleCommand {
+        match command {
+            Command::Simple(simple) => simple,
+            _ => panic!("simple command expected"),
+        }
+    }
+
+    fn command_words(command: &Command) -> (String, Vec<String>) {
+        let simple = simple_command(command);
+        let mut words = simple.words.iter().map(Word::unquoted);
+        (words.next().unwrap(), words.collect())
+    }
+
+    fn single_pipeline(list: CommandList) -> Vec<(String, Vec<String>)> {
+        assert_eq!(list.items.len(), 1);
+        list.items[0].1.commands.iter().map(command_words).collect()
+    }
+
     #[test]
     fn test_parse_args() {
         let mut parser = ArgParser::new();
         let input = "echo eins   zwei drei   ";
 
-        let commands = parser.parse_args(input).unwrap();
+        let commands = single_pipeline(parser.parse_args(input).unwrap());
         assert_eq!(commands.len(), 1);
         let (command, args) = &commands[0];
         assert_eq!(command, "echo");
@@ -198,7 +1603,7 @@ mod tests {
         let mut parser = ArgParser::new();
         let input = "echo 'eins   zwei' drei   ";
 
-        let commands = parser.parse_args(input).unwrap();
+        let commands = single_pipeline(parser.parse_args(input).unwrap());
         assert_eq!(commands.len(), 1);
         let (command, args) = &commands[0];
         assert_eq!(command, "echo");
@@ -210,7 +1615,7 @@ mod tests {
         let mut parser = ArgParser::new();
         let input = r#"echo "eins   'zwei' " drei   "#;
 
-        let commands = parser.parse_args(input).unwrap();
+        let commands = single_pipeline(parser.parse_args(input).unwrap());
         assert_eq!(commands.len(), 1);
         let (command, args) = &commands[0];
         assert_eq!(command, "echo");
@@ -218,6 +1623,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::assertions_on_constants)]
     fn test_parse_empty_args() {
         let mut parser = ArgParser::new();
         let input = "";
@@ -233,7 +1639,7 @@ mod tests {
         let mut parser = ArgParser::new();
         let input = r#"echo \'\"script world\"\'"#;
 
-        let commands = parser.parse_args(input).unwrap();
+        let commands = single_pipeline(parser.parse_args(input).unwrap());
         assert_eq!(commands.len(), 1);
         let (command, args) = &commands[0];
         assert_eq!(command, "echo");
@@ -245,10 +1651,723 @@ mod tests {
         let mut parser = ArgParser::new();
         let input = "echo eins | echo zwei | echo drei";
 
-        let commands = parser.parse_args(input).unwrap();
+        let commands = single_pipeline(parser.parse_args(input).unwrap());
         assert_eq!(commands.len(), 3);
         assert_eq!(commands[0].0, "echo");
         assert_eq!(commands[1].0, "echo");
         assert_eq!(commands[2].0, "echo");
     }
+
+    #[test]
+    fn test_command_list() {
+        let mut parser = ArgParser::new();
+        let input = "cd build; make&&./run || echo failed | wc -l;";
+
+        let list = parser.parse_args(input).unwrap();
+        let operators: Vec<ListOperator> = list.items.iter().map(|(op, _)| *op).collect();
+        assert_eq!(
+            operators,
+            vec![ListOperator::Seq, ListOperator::Seq, ListOperator::And, ListOperator::Or]
+        );
+        assert_eq!(command_words(&list.items[1].1.commands[0]).0, "make");
+        assert_eq!(command_words(&list.items[2].1.commands[0]).0, "./run");
+        assert_eq!(list.items[3].1.commands.len(), 2);
+        assert_eq!(
+            command_words(&list.items[3].1.commands[1]),
+            ("wc".to_string(), vec!["-l".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_quoted_operators() {
+        let mut parser = ArgParser::new();
+        let input = "echo 'a && b' \\; \"|\"";
+
+        let commands = single_pipeline(parser.parse_args(input).unwrap());
+        assert_eq!(commands[0].1, vec!["a && b", ";", "|"]);
+    }
+
+    #[test]
+    fn test_list_syntax_errors() {
+        let mut parser = ArgParser::new();
+        for input in ["&& ls", "ls &&", "ls | | wc", "ls ;; pwd", "; ls"] {
+            assert!(parser.parse_args(input).is_err(), "error expected for {input}");
+        }
+    }
+
+    #[test]
+    fn test_word_parts_and_spans() {
+        let mut parser = ArgParser::new();
+        let input = "echo a\\*'b c'\"d\\\"\" > out.txt";
+
+        let list = parser.parse_args(input).unwrap();
+        assert_eq!(list.span, Span::new(0, input.len()));
+        let simple = simple_command(&list.items[0].1.commands[0]);
+        assert_eq!(simple.span, Span::new(0, input.len()));
+        assert_eq!(simple.words.len(), 2);
+        assert_eq!(simple.words[0].span, Span::new(0, 4));
+        assert_eq!(
+            simple.words[1].parts,
+            vec![
+                WordPart::Literal("a".to_string()),
+                WordPart::Escaped('*'),
+                WordPart::SingleQuoted("b c".to_string()),
+                WordPart::DoubleQuoted(vec![WordPart::Literal("d\"".to_string())]),
+            ]
+        );
+        assert_eq!(simple.words[1].span, Span::new(5, 18));
+
+        assert_eq!(simple.redirects.len(), 1);
+        let redirect = &simple.redirects[0];
+        assert_eq!((redirect.fd, redirect.op), (1, RedirectOp::Output));
+        assert_eq!(redirect.target.unquoted(), "out.txt");
+        assert_eq!(redirect.span, Span::new(19, input.len()));
+    }
+
+    #[test]
+    fn test_parameter_expansions() {
+        let mut parser = ArgParser::new();
+        let input = "echo $HOME-$1$12 \"${x:-a b}\" '$y' ${z+$w} $ \"$\"";
+
+        let list = parser.parse_args(input).unwrap();
+        let simple = simple_command(&list.items[0].1.commands[0]);
+        let parameter = |name: &str, op| {
+            WordPart::Parameter(ParameterExpansion {
+                name: name.to_string(),
+                op,
+            })
+        };
+        assert_eq!(
+            simple.words[1].parts,
+            vec![
+                parameter("HOME", ParameterOp::Value),
+                WordPart::Literal("-".to_string()),
+                parameter("1", ParameterOp::Value),
+                parameter("1", ParameterOp::Value),
+                WordPart::Literal("2".to_string()),
+            ]
+        );
+        let default = Word {
+            parts: vec![WordPart::Literal("a b".to_string())],
+            span: Span::new(23, 26),
+        };
+        assert_eq!(
+            simple.words[2].parts,
+            vec![WordPart::DoubleQuoted(vec![parameter(
+                "x",
+                ParameterOp::Default { word: default, check_null: true }
+            )])]
+        );
+        assert_eq!(simple.words[3].parts, vec![WordPart::SingleQuoted("$y".to_string())]);
+        let alternative = Word {
+            parts: vec![parameter("w", ParameterOp::Value)],
+            span: Span::new(38, 40),
+        };
+        assert_eq!(
+            simple.words[4].parts,
+            vec![parameter("z", ParameterOp::Alternative { word: alternative, check_null: false })]
+        );
+        assert_eq!(simple.words[5].unquoted(), "$");
+        assert_eq!(simple.words[6].unquoted(), "$");
+    }
+
+    #[test]
+    fn test_invalid_parameter_expansions() {
+        let mut parser = ArgParser::new();
+        for input in ["echo ${}", "echo ${x!}", "echo ${#x-}", "echo ${:-x}"] {
+            assert!(parser.parse_args(input).is_err(), "error expected for {input}");
+        }
+        for input in ["echo ${x", "echo \"${x:-y\""] {
+            let err = parser.parse_args(input).unwrap_err();
+            assert!(err.is::<IncompleteInput>(), "incomplete input expected for {input}");
+        }
+    }
+
+    #[test]
+    fn test_command_substitutions() {
+        let mut parser = ArgParser::new();
+        let input = "echo $(cat $(echo f) | wc -l) \"`echo \\\"a\\\"`\" $( ) x";
+
+        let list = parser.parse_args(input).unwrap();
+        assert_eq!(list.items.len(), 1);
+        let simple = simple_command(&list.items[0].1.commands[0]);
+        assert_eq!(simple.words.len(), 5);
+        let WordPart::CommandSubstitution(outer) = &simple.words[1].parts[0] else {
+            panic!("command substitution expected");
+        };
+        assert_eq!(outer.items[0].1.commands.len(), 2);
+        assert_eq!(outer.span, Span::new(7, 28));
+        let (name, args) = command_words(&outer.items[0].1.commands[0]);
+        assert_eq!((name.as_str(), args.len()), ("cat", 1));
+
+        let WordPart::DoubleQuoted(quoted) = &simple.words[2].parts[0] else {
+            panic!("double quoted string expected");
+        };
+        let WordPart::CommandSubstitution(backquoted) = &quoted[0] else {
+            panic!("command substitution expected");
+        };
+        let inner = simple_command(&backquoted.items[0].1.commands[0]);
+        assert_eq!(inner.words[1].unquoted(), "a");
+
+        let WordPart::CommandSubstitution(empty) = &simple.words[3].parts[0] else {
+            panic!("command substitution expected");
+        };
+        assert!(empty.items.is_empty());
+    }
+
+    #[test]
+    fn test_unterminated_command_substitution() {
+        let mut parser = ArgParser::new();
+        for input in ["echo $(echo a", "echo `echo a", "echo \"$(echo a\" b"] {
+            let err = parser.parse_args(input).unwrap_err();
+            assert!(err.is::<IncompleteInput>(), "incomplete input expected for {input}");
+        }
+        for input in ["echo a)", "echo $(echo a))", "echo (a)"] {
+            assert!(parser.parse_args(input).is_err(), "error expected for {input}");
+        }
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let mut parser = ArgParser::new();
+        let input = "echo $(( (1 + $x) * 2 ))$((y)); ((i++)) > /dev/null";
+
+        let list = parser.parse_args(input).unwrap();
+        let simple = simple_command(&list.items[0].1.commands[0]);
+        let WordPart::Arithmetic(expression) = &simple.words[1].parts[0] else {
+            panic!("arithmetic expansion expected");
+        };
+        assert_eq!(expression.unquoted(), " (1 + $x) * 2 ");
+        assert_eq!(expression.span, Span::new(8, 22));
+        assert!(matches!(simple.words[1].parts[1], WordPart::Arithmetic(_)));
+
+        let Command::Arithmetic(arithmetic) = &list.items[1].1.commands[0] else {
+            panic!("arithmetic command expected");
+        };
+        assert_eq!(arithmetic.expression.unquoted(), "i++");
+        assert_eq!(arithmetic.redirects.len(), 1);
+        assert_eq!(arithmetic.span, Span::new(32, input.len()));
+
+        let err = parser.parse_args("echo $((1 + (2").unwrap_err();
+        assert!(err.is::<IncompleteInput>());
+    }
+
+    #[test]
+    fn test_extended_patterns_are_words() {
+        let mut parser = ArgParser::new().extglob(true);
+        let input = "ls !(*.rs) *.@(a|b(c)) x|wc ?(";
+
+        let list = parser.parse_args(input);
+        assert!(list.is_err(), "unbalanced extended pattern is no word");
+        let list = parser.parse_args(&input[..input.len() - 3]).unwrap();
+        assert_eq!(
+            single_pipeline(list),
+            vec![
+                ("ls".to_string(), vec!["!(*.rs)".to_string(), "*.@(a|b(c))".to_string(), "x".to_string()]),
+                ("wc".to_string(), vec![]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_extended_patterns_need_extglob() {
+        let mut parser = ArgParser::new();
+        let err = parser.parse_args("ls !(*.rs)").unwrap_err();
+        assert_eq!(err.to_string(), "syntax error near unexpected token `('");
+        let list = parser.parse_args("echo a@b +x").unwrap();
+        assert_eq!(
+            single_pipeline(list),
+            vec![("echo".to_string(), vec!["a@b".to_string(), "+x".to_string()])]
+        );
+    }
+
+    #[test]
+    fn test_incomplete_input() {
+        let mut parser = ArgParser::new();
+        for input in ["echo 'a", "echo \"a", "echo a |", "echo a &&", "echo a ||\n", "echo a \\"] {
+            let err = parser.parse_args(input).unwrap_err();
+            assert!(err.is::<IncompleteInput>(), "incomplete input expected for {input}");
+        }
+        for input in ["echo `echo a |`", "| echo a", "echo a ;;"] {
+            let err = parser.parse_args(input).unwrap_err();
+            assert!(!err.is::<IncompleteInput>(), "syntax error expected for {input}");
+        }
+
+        let list = parser.parse_args("echo 'a\nb' a\\\nb \\\n| wc").unwrap();
+        assert_eq!(
+            single_pipeline(list),
+            vec![
+                ("echo".to_string(), vec!["a\nb".to_string(), "ab".to_string()]),
+                ("wc".to_string(), vec![]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_error_rendering() {
+        let mut parser = ArgParser::new();
+        let render = |parser: &mut ArgParser, input: &str| {
+            let err = parser.parse_args(input).unwrap_err();
+            err.downcast_ref::<ParseError>().expect("parse error expected").render(input)
+        };
+
+        assert_eq!(
+            render(&mut parser, "echo a | | wc"),
+            "syntax error near unexpected token `|'\necho a | | wc\n         ^"
+        );
+        assert_eq!(
+            render(&mut parser, "echo ä\necho ö >> ;;"),
+            "line 2: syntax error near unexpected token `;;'\necho ö >> ;;\n          ^^"
+        );
+        assert_eq!(
+            render(&mut parser, "echo ${x!}"),
+            "bad substitution\necho ${x!}\n         ^"
+        );
+        let err = parser.parse_args(" # comment").unwrap_err();
+        assert!(matches!(err.downcast_ref::<ParseError>(), Some(ParseError::EmptyInput)));
+    }
+
+    #[test]
+    fn test_if_clause() {
+        let mut parser = ArgParser::new();
+        let input = "if a; then b\nelif c\nthen d; else e; f; fi > out | wc";
+
+        let list = parser.parse_args(input).unwrap();
+        let pipeline = &list.items[0].1;
+        let Command::If(if_clause) = &pipeline.commands[0] else {
+            panic!("if clause expected");
+        };
+        assert_eq!(if_clause.branches.len(), 2);
+        assert_eq!(command_words(&if_clause.branches[1].0.items[0].1.commands[0]).0, "c");
+        assert_eq!(command_words(&if_clause.branches[1].1.items[0].1.commands[0]).0, "d");
+        assert_eq!(if_clause.else_branch.as_ref().unwrap().items.len(), 2);
+        assert_eq!(if_clause.redirects[0].target.unquoted(), "out");
+        assert_eq!(if_clause.span, Span::new(0, 47));
+        assert_eq!(command_words(&pipeline.commands[1]).0, "wc");
+
+        let list = parser.parse_args("echo if then fi").unwrap();
+        assert_eq!(single_pipeline(list)[0].1, vec!["if", "then", "fi"]);
+
+        for input in ["if a; fi", "if a; then fi", "fi", "if a; then b; fi c", "if a; then b; else c; elif d; fi"] {
+            let err = parser.parse_args(input).unwrap_err();
+            assert!(err.is::<ParseError>(), "syntax error expected for {input}");
+        }
+        for input in ["if a", "if a; then b", "if a; then b; else"] {
+            let err = parser.parse_args(input).unwrap_err();
+            assert!(err.is::<IncompleteInput>(), "incomplete input expected for {input}");
+        }
+    }
+
+    #[test]
+    fn test_loops() {
+        let mut parser = ArgParser::new();
+
+        let list = parser.parse_args("until a; b\ndo c; done < in").unwrap();
+        let Command::While(while_loop) = &list.items[0].1.commands[0] else {
+            panic!("while loop expected");
+        };
+        assert!(while_loop.until);
+        assert_eq!(while_loop.condition.items.len(), 2);
+        assert_eq!(command_words(&while_loop.body.items[0].1.commands[0]).0, "c");
+        assert_eq!(while_loop.redirects[0].target.unquoted(), "in");
+
+        let list = parser.parse_args("for x in a 'b c'\ndo echo $x; done").unwrap();
+        let Command::For(for_loop) = &list.items[0].1.commands[0] else {
+            panic!("for loop expected");
+        };
+        assert_eq!(for_loop.name, "x");
+        let words: Vec<String> = for_loop.words.as_ref().unwrap().iter().map(Word::unquoted).collect();
+        assert_eq!(words, vec!["a", "b c"]);
+
+        for input in ["for x; do :; done", "for x do :; done", "for x\ndo :; done"] {
+            let list = parser.parse_args(input).unwrap();
+            let Command::For(for_loop) = &list.items[0].1.commands[0] else {
+                panic!("for loop expected");
+            };
+            assert_eq!(for_loop.words, None);
+        }
+
+        let list = parser.parse_args("for ((i = 0; i < (3); i++)) do :; done").unwrap();
+        let Command::ArithmeticFor(for_loop) = &list.items[0].1.commands[0] else {
+            panic!("arithmetic for loop expected");
+        };
+        assert_eq!(for_loop.init.unquoted(), "i = 0");
+        assert_eq!(for_loop.condition.unquoted(), " i < (3)");
+        assert_eq!(for_loop.update.unquoted(), " i++");
+        assert_eq!(for_loop.condition.span, Span::new(12, 20));
+
+        for input in ["while a; done", "for 1 in a; do b; done", "for ((i; i)); do b; done", "do b; done"] {
+            let err = parser.parse_args(input).unwrap_err();
+            assert!(err.is::<ParseError>(), "syntax error expected for {input}");
+        }
+        for input in ["while a", "while a; do b", "for x in a", "for ((;;)); do"] {
+            let err = parser.parse_args(input).unwrap_err();
+            assert!(err.is::<IncompleteInput>(), "incomplete input expected for {input}");
+        }
+    }
+
+    #[test]
+    fn test_function_definitions() {
+        let mut parser = ArgParser::new();
+
+        for input in ["f() { a; b; }", "function f { a; b; }", "function f()\n{\na\nb\n}"] {
+            let list = parser.parse_args(input).unwrap();
+            let Command::FunctionDefinition(function) = &list.items[0].1.commands[0] else {
+                panic!("function definition expected for {input}");
+            };
+            assert_eq!(function.name, "f");
+            let Command::Group(group) = function.body.as_ref() else {
+                panic!("group expected for {input}");
+            };
+            assert_eq!(group.body.items.len(), 2);
+        }
+
+        let list = parser.parse_args("f () { echo }; } > out; f").unwrap();
+        let Command::FunctionDefinition(function) = &list.items[0].1.commands[0] else {
+            panic!("function definition expected");
+        };
+        let Command::Group(group) = function.body.as_ref() else {
+            panic!("group expected");
+        };
+        assert_eq!(command_words(&group.body.items[0].1.commands[0]).1, vec!["}"]);
+        assert_eq!(group.redirects[0].target.unquoted(), "out");
+        assert_eq!(function.span, Span::new(0, 22));
+
+        let list = parser.parse_args("f() (cd dir)").unwrap();
+        let Command::FunctionDefinition(function) = &list.items[0].1.commands[0] else {
+            panic!("function definition expected");
+        };
+        assert!(matches!(function.body.as_ref(), Command::Subshell(_)));
+
+        for input in ["f() echo", "'f'() { a; }", "if() { a; }", "f a() { b; }", "}"] {
+            let err = parser.parse_args(input).unwrap_err();
+            assert!(err.is::<ParseError>(), "syntax error expected for {input}");
+        }
+        for input in ["f() {", "f() { a", "function"] {
+            let err = parser.parse_args(input).unwrap_err();
+            assert!(err.is::<IncompleteInput>(), "incomplete input expected for {input}");
+        }
+    }
+
+    #[test]
+    fn test_subshells_and_groups() {
+        let mut parser = ArgParser::new();
+
+        let list = parser.parse_args("(cd a && b) > out | { c; d\n} 2> err").unwrap();
+        let pipeline = &list.items[0].1;
+        let Command::Subshell(subshell) = &pipeline.commands[0] else {
+            panic!("subshell expected");
+        };
+        assert_eq!(subshell.body.items.len(), 2);
+        assert_eq!(subshell.redirects[0].target.unquoted(), "out");
+        assert_eq!(subshell.span, Span::new(0, 17));
+        let Command::Group(group) = &pipeline.commands[1] else {
+            panic!("group expected");
+        };
+        assert_eq!(group.body.items.len(), 2);
+        assert_eq!(group.redirects[0].fd, 2);
+
+        let list = parser.parse_args("((cd a); b)").unwrap();
+        let Command::Subshell(subshell) = &list.items[0].1.commands[0] else {
+            panic!("subshell expected");
+        };
+        assert_eq!(subshell.span, Span::new(0, 11));
+        assert!(matches!(subshell.body.items[0].1.commands[0], Command::Subshell(_)));
+
+        let list = parser.parse_args("echo $( (a) ) {a,b} {").unwrap();
+        let simple = simple_command(&list.items[0].1.commands[0]);
+        let WordPart::CommandSubstitution(commands) = &simple.words[1].parts[0] else {
+            panic!("command substitution expected");
+        };
+        assert!(matches!(commands.items[0].1.commands[0], Command::Subshell(_)));
+        assert_eq!(simple.words.len(), 4);
+
+        for input in ["()", "(a) b", "{ a; } b", "{a; }", "}"] {
+            let err = parser.parse_args(input).unwrap_err();
+            assert!(err.is::<ParseError>(), "syntax error expected for {input}");
+        }
+        for input in ["(a", "{ a; }; (", "{ a", "{ a }"] {
+            let err = parser.parse_args(input).unwrap_err();
+            assert!(err.is::<IncompleteInput>(), "incomplete input expected for {input}");
+        }
+    }
+
+    #[test]
+    fn test_alias_expansion() {
+        let aliases = [
+            ("ll", "ls -l"),
+            ("s", "sudo "),
+            ("ls", "ls -F"),
+            ("a", "b"),
+            ("b", "a"),
+            ("loop", "for x in 1; do"),
+            ("c", "cat"),
+        ];
+        let aliases = aliases.iter().map(|(name, text)| (name.to_string(), text.to_string())).collect();
+        let mut parser = ArgParser::new().aliases(aliases);
+
+        let list = parser.parse_args("ll x | s ll 'll' && \\ll; a; echo ll").unwrap();
+        let commands: Vec<(String, Vec<String>)> = list
+            .items
+            .iter()
+            .flat_map(|(_, pipeline)| pipeline.commands.iter().map(command_words))
+            .collect();
+        let expected = [
+            ("ls", vec!["-F", "-l", "x"]),
+            ("sudo", vec!["ls", "-F", "-l", "ll"]),
+            ("ll", vec![]),
+            ("a", vec![]),
+            ("echo", vec!["ll"]),
+        ];
+        let expected: Vec<(String, Vec<String>)> = expected
+            .iter()
+            .map(|(name, args)| (name.to_string(), args.iter().map(|arg| arg.to_string()).collect()))
+            .collect();
+        assert_eq!(commands, expected);
+
+        let list = parser.parse_args("loop c; done").unwrap();
+        assert!(matches!(list.items[0].1.commands[0], Command::For(_)));
+
+        let list = parser.parse_args("cat <<A; c <<B\nfirst\nA\nsecond\nB").unwrap();
+        let here_doc = |idx: usize| simple_command(&list.items[idx].1.commands[0]).redirects[0].target.unquoted();
+        assert_eq!((here_doc(0), here_doc(1)), ("first\n".to_string(), "second\n".to_string()));
+
+        let input = "ll; c )";
+        let err = parser.parse_args(input).unwrap_err();
+        let err = err.downcast_ref::<ParseError>().unwrap();
+        assert_eq!(err.render(input), "syntax error near unexpected token `)'\nll; c )\n      ^");
+    }
+
+    #[test]
+    fn test_case_clause() {
+        let mut parser = ArgParser::new();
+        let input = "case $x in\n(a|b*) echo a;;\n c) ;& d) echo d\n;;& *)\necho e\nesac";
+
+        let list = parser.parse_args(input).unwrap();
+        let Command::Case(case_clause) = &list.items[0].1.commands[0] else {
+            panic!("case clause expected");
+        };
+        assert_eq!(case_clause.word.unquoted(), "$x");
+        let items: Vec<_> = case_clause
+            .items
+            .iter()
+            .map(|item| {
+                let patterns: Vec<_> = item.patterns.iter().map(Word::unquoted).collect();
+                (patterns, item.body.items.len(), item.terminator)
+            })
+            .collect();
+        assert_eq!(
+            items,
+            vec![
+                (vec!["a".to_string(), "b*".to_string()], 1, CaseTerminator::Break),
+                (vec!["c".to_string()], 0, CaseTerminator::FallThrough),
+                (vec!["d".to_string()], 1, CaseTerminator::Continue),
+                (vec!["*".to_string()], 1, CaseTerminator::Break),
+            ]
+        );
+        let spans: Vec<_> = case_clause.items.iter().map(|item| &input[item.span.start..item.span.end]).collect();
+        assert_eq!(spans, vec!["(a|b*) echo a;;", "c) ;&", "d) echo d\n;;&", "*)\necho e"]);
+
+        let list = parser.parse_args("case x in esac").unwrap();
+        let Command::Case(case_clause) = &list.items[0].1.commands[0] else {
+            panic!("case clause expected");
+        };
+        assert!(case_clause.items.is_empty());
+
+        for input in ["case x in a) b;; ) c;; esac", "case x a) b;; esac", "echo ;;"] {
+            let err = parser.parse_args(input).unwrap_err();
+            assert!(err.is::<ParseError>(), "syntax error expected for {input}");
+        }
+        for input in ["case x in", "case x in a) b;;", "case x in a|"] {
+            let err = parser.parse_args(input).unwrap_err();
+            assert!(err.is::<IncompleteInput>(), "incomplete input expected for {input}");
+        }
+    }
+
+    #[test]
+    fn test_comments() {
+        let mut parser = ArgParser::new();
+        let input = "echo a#b '#c' # d | wc\n# e\necho $(echo f # g\n)";
+
+        let list = parser.parse_args(input).unwrap();
+        assert_eq!(list.items.len(), 2);
+        let words: Vec<_> = list.items[0].1.commands.iter().map(command_words).collect();
+        assert_eq!(words, vec![("echo".to_string(), vec!["a#b".to_string(), "#c".to_string()])]);
+        let err = parser.parse_args("  # only a comment").unwrap_err();
+        assert!(matches!(err.downcast_ref::<ParseError>(), Some(ParseError::EmptyInput)));
+
+        let mut parser = ArgParser::new().comments(false);
+        let list = parser.parse_args("echo a # b").unwrap();
+        assert_eq!(
+            single_pipeline(list),
+            vec![("echo".to_string(), vec!["a".to_string(), "#".to_string(), "b".to_string()])]
+        );
+    }
+
+    #[test]
+    fn test_spans_are_byte_offsets() {
+        let mut parser = ArgParser::new();
+        let input = "echo grüße | wc";
+
+        let list = parser.parse_args(input).unwrap();
+        let pipeline = &list.items[0].1;
+        assert_eq!(pipeline.commands[0].span(), Span::new(0, 12));
+        assert_eq!(pipeline.commands[1].span(), Span::new(15, 17));
+    }
+
+    #[test]
+    fn test_quoted_redirection_is_argument() {
+        let mut parser = ArgParser::new();
+        let input = "echo '>' foo";
+
+        let list = parser.parse_args(input).unwrap();
+        let simple = simple_command(&list.items[0].1.commands[0]);
+        assert!(simple.redirects.is_empty());
+        assert_eq!(simple.words.len(), 3);
+    }
+
+    #[test]
+    fn test_leading_assignments() {
+        let mut parser = ArgParser::new();
+        let input = "A=1 'B'=2 >out C=\"x y\" env D=4; E=5";
+
+        let list = parser.parse_args(input).unwrap();
+        let simple = simple_command(&list.items[0].1.commands[0]);
+        let assignments: Vec<String> = simple.assignments.iter().map(Word::unquoted).collect();
+        assert_eq!(assignments, vec!["A=1"]);
+        let words: Vec<String> = simple.words.iter().map(Word::unquoted).collect();
+        assert_eq!(words, vec!["B=2", "C=x y", "env", "D=4"]);
+        let simple = simple_command(&list.items[1].1.commands[0]);
+        assert_eq!(simple.assignments.len(), 1);
+        assert!(simple.words.is_empty());
+        assert_eq!(simple.span, Span::new(32, 35));
+    }
+
+    #[test]
+    fn test_glued_redirections() {
+        let mut parser = ArgParser::new();
+        let input = "echo hi>out.txt a2>>log 2>err '2'>x \\>y";
+
+        let list = parser.parse_args(input).unwrap();
+        let simple = simple_command(&list.items[0].1.commands[0]);
+        let words: Vec<String> = simple.words.iter().map(Word::unquoted).collect();
+        assert_eq!(words, vec!["echo", "hi", "a2", "2", ">y"]);
+        let redirects: Vec<(i32, RedirectOp, String)> = simple
+            .redirects
+            .iter()
+            .map(|r| (r.fd, r.op, r.target.unquoted()))
+            .collect();
+        assert_eq!(
+            redirects,
+            vec![
+                (1, RedirectOp::Output, "out.txt".to_string()),
+                (1, RedirectOp::Append, "log".to_string()),
+                (2, RedirectOp::Output, "err".to_string()),
+                (1, RedirectOp::Output, "x".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_redirection_without_target() {
+        let mut parser = ArgParser::new();
+        assert!(parser.parse_args("echo hi >").is_err());
+        assert!(parser.parse_args("echo hi > | wc").is_err());
+    }
+
+    #[test]
+    fn test_input_and_fd_redirections() {
+        let mut parser = ArgParser::new();
+        let input = "cmd <in.txt 3>log 4< input 1<>rw";
+
+        let list = parser.parse_args(input).unwrap();
+        let simple = simple_command(&list.items[0].1.commands[0]);
+        assert_eq!(simple.words.len(), 1);
+        let redirects: Vec<(i32, RedirectOp, String)> = simple
+            .redirects
+            .iter()
+            .map(|r| (r.fd, r.op, r.target.unquoted()))
+            .collect();
+        assert_eq!(
+            redirects,
+            vec![
+                (0, RedirectOp::Input, "in.txt".to_string()),
+                (3, RedirectOp::Output, "log".to_string()),
+                (4, RedirectOp::Input, "input".to_string()),
+                (1, RedirectOp::ReadWrite, "rw".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_duplication_redirections() {
+        let mut parser = ArgParser::new();
+        let input = "cmd 2>&1 >&2 3>&- <&4 &>all &>>log |& wc";
+
+        let list = parser.parse_args(input).unwrap();
+        let pipeline = &list.items[0].1;
+        assert_eq!(pipeline.commands.len(), 2);
+        let simple = simple_command(&pipeline.commands[0]);
+        let redirects: Vec<(i32, RedirectOp, String)> = simple
+            .redirects
+            .iter()
+            .map(|r| (r.fd, r.op, r.target.unquoted()))
+            .collect();
+        assert_eq!(
+            redirects,
+            vec![
+                (2, RedirectOp::DupOutput, "1".to_string()),
+                (1, RedirectOp::DupOutput, "2".to_string()),
+                (3, RedirectOp::DupOutput, "-".to_string()),
+                (0, RedirectOp::DupInput, "4".to_string()),
+                (1, RedirectOp::OutputAll, "all".to_string()),
+                (1, RedirectOp::AppendAll, "log".to_string()),
+                (2, RedirectOp::DupOutput, "1".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_here_documents() {
+        let mut parser = ArgParser::new();
+        let input = "cat <<EOF; cat <<-'END'\nline \\$x\nEOF\n\t\tindented\n\tEND\necho done";
+
+        let list = parser.parse_args(input).unwrap();
+        assert_eq!(list.items.len(), 3);
+        let first = simple_command(&list.items[0].1.commands[0]);
+        assert_eq!(first.redirects[0].op, RedirectOp::HereDoc { strip_tabs: false });
+        assert_eq!(
+            first.redirects[0].target.parts,
+            vec![WordPart::DoubleQuoted(vec![WordPart::Literal("line $x\n".to_string())])]
+        );
+        let second = simple_command(&list.items[1].1.commands[0]);
+        assert_eq!(second.redirects[0].op, RedirectOp::HereDoc { strip_tabs: true });
+        assert_eq!(
+            second.redirects[0].target.parts,
+            vec![WordPart::SingleQuoted("indented\n".to_string())]
+        );
+        assert_eq!(command_words(&list.items[2].1.commands[0]).0, "echo");
+    }
+
+    #[test]
+    fn test_unterminated_here_document_is_incomplete() {
+        let mut parser = ArgParser::new();
+        for input in ["cat <<EOF", "cat <<EOF\nline"] {
+            let err = parser.parse_args(input).unwrap_err();
+            assert!(err.is::<IncompleteInput>(), "incomplete input expected for {input}");
+        }
+    }
+
+    #[test]
+    fn test_here_string_and_newlines() {
+        let mut parser = ArgParser::new();
+        let input = "\nwc -c <<< \"a b\"\n\necho x |\nwc";
+
+        let list = parser.parse_args(input).unwrap();
+        assert_eq!(list.items.len(), 2);
+        let simple = simple_command(&list.items[0].1.commands[0]);
+        assert_eq!(simple.redirects[0].op, RedirectOp::HereString);
+        assert_eq!(simple.redirects[0].target.unquoted(), "a b");
+        assert_eq!(list.items[1].1.commands.len(), 2);
+    }
 }
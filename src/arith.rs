@@ -0,0 +1,491 @@
+use crate::shell::Shell;
+use anyhow::{anyhow, Result};
+
+/// Maximum nesting of variables whose values are expressions themselves.
+const MAX_RECURSION: usize = 64;
+
+/// Evaluates an arithmetic expression like the one in `$((...))`. Variables
+/// are referenced by name; unset or empty variables count as 0.
+pub fn evaluate(expression: &str, shell: &mut Shell) -> Result<i64> {
+    evaluate_nested(expression, shell, 0)
+}
+
+fn evaluate_nested(expression: &str, shell: &mut Shell, depth: usize) -> Result<i64> {
+    if depth > MAX_RECURSION {
+        return Err(anyhow!(
+            "{}: expression recursion level exceeded",
+            expression.trim()
+        ));
+    }
+    let result = tokenize(expression)
+        .and_then(|tokens| ExprParser { tokens, pos: 0 }.parse())
+        .and_then(|expr| {
+            let mut evaluator = Evaluator { shell, depth };
+            match expr {
+                Some(expr) => evaluator.eval(&expr),
+                None => Ok(0),
+            }
+        });
+    result.map_err(|err| anyhow!("{}: {}", expression.trim(), err))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Op(&'static str),
+}
+
+/// Operators ordered such that longer ones are found first.
+const OPERATORS: [&str; 39] = [
+    "<<=", ">>=", "**", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "++", "--", "*=", "/=",
+    "%=", "+=", "-=", "&=", "^=", "|=", "+", "-", "*", "/", "%", "<", ">", "&", "|", "^", "!", "~",
+    "?", ":", "=", ",", "(", ")",
+];
+
+fn tokenize(expression: &str) -> Result<Vec<Token>> {
+    let chars: Vec<char> = expression.chars().collect();
+    let mut tokens = vec![];
+    let mut pos = 0;
+
+    while pos < chars.len() {
+        let ch = chars[pos];
+        if ch.is_whitespace() {
+            pos += 1;
+        } else if ch.is_ascii_alphanumeric() || ch == '_' {
+            // Digits of numbers in a base up to 64 include `@` and `_`
+            let is_number = ch.is_ascii_digit();
+            let start = pos;
+            while pos < chars.len()
+                && (chars[pos].is_ascii_alphanumeric()
+                    || chars[pos] == '_'
+                    || (is_number && "#@".contains(chars[pos])))
+            {
+                pos += 1;
+            }
+            let word: String = chars[start..pos].iter().collect();
+            if is_number {
+                tokens.push(Token::Number(parse_number(&word)?));
+            } else {
+                tokens.push(Token::Name(word));
+            }
+        } else {
+            let rest: String = chars[pos..chars.len().min(pos + 3)].iter().collect();
+            let op = OPERATORS
+                .iter()
+                .find(|op| rest.starts_with(*op))
+                .ok_or_else(|| syntax_error(&chars[pos..].iter().collect::<String>()))?;
+            tokens.push(Token::Op(op));
+            pos += op.len();
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Parses a decimal, octal (`017`), hexadecimal (`0x1f`) or `base#digits`
+/// literal.
+fn parse_number(literal: &str) -> Result<i64> {
+    let (base, digits) = if let Some((base, digits)) = literal.split_once('#') {
+        let base = base
+            .parse::<u32>()
+            .ok()
+            .filter(|base| (2..=64).contains(base))
+            .ok_or_else(|| anyhow!("invalid arithmetic base (error token is \"{literal}\")"))?;
+        (base, digits)
+    } else if let Some(digits) = literal
+        .strip_prefix("0x")
+        .or_else(|| literal.strip_prefix("0X"))
+    {
+        (16, digits)
+    } else if literal.len() > 1 && literal.starts_with('0') {
+        (8, &literal[1..])
+    } else {
+        (10, literal)
+    };
+
+    let mut value: i64 = 0;
+    for ch in digits.chars() {
+        let digit = match ch {
+            '0'..='9' => ch as u32 - '0' as u32,
+            'a'..='z' => ch as u32 - 'a' as u32 + 10,
+            'A'..='Z' if base <= 36 => ch as u32 - 'A' as u32 + 10,
+            'A'..='Z' => ch as u32 - 'A' as u32 + 36,
+            '@' => 62,
+            '_' => 63,
+            _ => u32::MAX,
+        };
+        if digit >= base {
+            return Err(anyhow!(
+                "value too great for base (error token is \"{literal}\")"
+            ));
+        }
+        value = value.wrapping_mul(base as i64).wrapping_add(digit as i64);
+    }
+    if digits.is_empty() {
+        return Err(anyhow!("invalid number (error token is \"{literal}\")"));
+    }
+    Ok(value)
+}
+
+fn syntax_error(token: &str) -> anyhow::Error {
+    anyhow!("syntax error in expression (error token is \"{token}\")")
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Number(i64),
+    Variable(String),
+    /// `-x`, `+x`, `!x` or `~x`
+    Unary(&'static str, Box<Expr>),
+    /// `++x` or `--x`, with the amount that is added
+    PreIncrement(String, i64),
+    /// `x++` or `x--`, with the amount that is added
+    PostIncrement(String, i64),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+    /// `x = y` or a compound assignment like `x += y` with its binary operator
+    Assign(String, Option<&'static str>, Box<Expr>),
+    /// `x ? y : z`
+    Conditional(Box<Expr>, Box<Expr>, Box<Expr>),
+}
+
+/// Binary operators from the lowest to the highest precedence. `**` is right
+/// associative and handled separately.
+const BINARY_LEVELS: [&[&str]; 10] = [
+    &["||"],
+    &["&&"],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["==", "!="],
+    &["<", ">", "<=", ">="],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+struct ExprParser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl ExprParser {
+    fn parse(mut self) -> Result<Option<Expr>> {
+        if self.tokens.is_empty() {
+            return Ok(None);
+        }
+        let expr = self.parse_comma()?;
+        match self.tokens.get(self.pos) {
+            None => Ok(Some(expr)),
+            Some(_) => Err(self.unexpected()),
+        }
+    }
+
+    fn parse_comma(&mut self) -> Result<Expr> {
+        let mut expr = self.parse_assignment()?;
+        while self.skip_op(",") {
+            expr = Expr::Binary(",", Box::new(expr), Box::new(self.parse_assignment()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_assignment(&mut self) -> Result<Expr> {
+        if let (Some(Token::Name(name)), Some(Token::Op(op))) =
+            (self.tokens.get(self.pos), self.tokens.get(self.pos + 1))
+        {
+            if op.ends_with('=') && !matches!(*op, "==" | "!=" | "<=" | ">=") {
+                let name = name.clone();
+                let binary_op = match *op {
+                    "=" => None,
+                    _ => Some(&op[..op.len() - 1]),
+                };
+                self.pos += 2;
+                let value = self.parse_assignment()?;
+                return Ok(Expr::Assign(name, binary_op, Box::new(value)));
+            }
+        }
+        self.parse_conditional()
+    }
+
+    fn parse_conditional(&mut self) -> Result<Expr> {
+        let condition = self.parse_binary(0)?;
+        if !self.skip_op("?") {
+            return Ok(condition);
+        }
+        let then_expr = self.parse_comma()?;
+        if !self.skip_op(":") {
+            return Err(self.unexpected());
+        }
+        let else_expr = self.parse_assignment()?;
+        Ok(Expr::Conditional(
+            Box::new(condition),
+            Box::new(then_expr),
+            Box::new(else_expr),
+        ))
+    }
+
+    fn parse_binary(&mut self, level: usize) -> Result<Expr> {
+        if level == BINARY_LEVELS.len() {
+            return self.parse_power();
+        }
+        let mut expr = self.parse_binary(level + 1)?;
+        while let Some(Token::Op(op)) = self.tokens.get(self.pos) {
+            if !BINARY_LEVELS[level].contains(op) {
+                break;
+            }
+            let op = *op;
+            self.pos += 1;
+            let rhs = self.parse_binary(level + 1)?;
+            expr = Expr::Binary(op, Box::new(expr), Box::new(rhs));
+        }
+        Ok(expr)
+    }
+
+    fn parse_power(&mut self) -> Result<Expr> {
+        let base = self.parse_unary()?;
+        if self.skip_op("**") {
+            let exponent = self.parse_power()?;
+            return Ok(Expr::Binary("**", Box::new(base), Box::new(exponent)));
+        }
+        Ok(base)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr> {
+        match self.tokens.get(self.pos).cloned() {
+            Some(Token::Op(op @ ("++" | "--"))) => {
+                if let Some(Token::Name(name)) = self.tokens.get(self.pos + 1).cloned() {
+                    self.pos += 2;
+                    let delta = if op == "++" { 1 } else { -1 };
+                    return Ok(Expr::PreIncrement(name, delta));
+                }
+                // Without a variable, `--x` is the same as `-(-x)`
+                let sign = if op == "++" { "+" } else { "-" };
+                self.pos += 1;
+                let operand = self.parse_unary()?;
+                Ok(Expr::Unary(
+                    sign,
+                    Box::new(Expr::Unary(sign, Box::new(operand))),
+                ))
+            }
+            Some(Token::Op(op @ ("-" | "+" | "!" | "~"))) => {
+                self.pos += 1;
+                Ok(Expr::Unary(op, Box::new(self.parse_unary()?)))
+            }
+            _ => self.parse_postfix(),
+        }
+    }
+
+    fn parse_postfix(&mut self) -> Result<Expr> {
+        match self.tokens.get(self.pos).cloned() {
+            Some(Token::Number(value)) => {
+                self.pos += 1;
+                Ok(Expr::Number(value))
+            }
+            Some(Token::Name(name)) => {
+                self.pos += 1;
+                match self.tokens.get(self.pos) {
+                    Some(Token::Op("++")) => {
+                        self.pos += 1;
+                        Ok(Expr::PostIncrement(name, 1))
+                    }
+                    Some(Token::Op("--")) => {
+                        self.pos += 1;
+                        Ok(Expr::PostIncrement(name, -1))
+                    }
+                    _ => Ok(Expr::Variable(name)),
+                }
+            }
+            Some(Token::Op("(")) => {
+                self.pos += 1;
+                let expr = self.parse_comma()?;
+                if !self.skip_op(")") {
+                    return Err(self.unexpected());
+                }
+                Ok(expr)
+            }
+            Some(_) => Err(self.unexpected()),
+            None => Err(anyhow!("syntax error: operand expected")),
+        }
+    }
+
+    fn skip_op(&mut self, op: &str) -> bool {
+        let found = matches!(self.tokens.get(self.pos), Some(Token::Op(found)) if *found == op);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn unexpected(&self) -> anyhow::Error {
+        let rest: Vec<String> = self.tokens[self.pos..]
+            .iter()
+            .map(|token| match token {
+                Token::Number(value) => value.to_string(),
+                Token::Name(name) => name.clone(),
+                Token::Op(op) => op.to_string(),
+            })
+            .collect();
+        if rest.is_empty() {
+            return anyhow!("syntax error: operand expected");
+        }
+        syntax_error(&rest.join(" "))
+    }
+}
+
+struct Evaluator<'a> {
+    shell: &'a mut Shell,
+    depth: usize,
+}
+
+impl Evaluator<'_> {
+    fn eval(&mut self, expr: &Expr) -> Result<i64> {
+        match expr {
+            Expr::Number(value) => Ok(*value),
+            Expr::Variable(name) => self.get_var(name),
+            Expr::Unary(op, operand) => {
+                let value = self.eval(operand)?;
+                Ok(match *op {
+                    "-" => value.wrapping_neg(),
+                    "!" => (value == 0) as i64,
+                    "~" => !value,
+                    _ => value,
+                })
+            }
+            Expr::PreIncrement(name, delta) => {
+                let value = self.get_var(name)?.wrapping_add(*delta);
+                self.set_var(name, value)?;
+                Ok(value)
+            }
+            Expr::PostIncrement(name, delta) => {
+                let value = self.get_var(name)?;
+                self.set_var(name, value.wrapping_add(*delta))?;
+                Ok(value)
+            }
+            Expr::Binary("&&", lhs, rhs) => {
+                Ok((self.eval(lhs)? != 0 && self.eval(rhs)? != 0) as i64)
+            }
+            Expr::Binary("||", lhs, rhs) => {
+                Ok((self.eval(lhs)? != 0 || self.eval(rhs)? != 0) as i64)
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                let lhs = self.eval(lhs)?;
+                let rhs = self.eval(rhs)?;
+                apply_binary(op, lhs, rhs)
+            }
+            Expr::Assign(name, op, value) => {
+                let mut value = self.eval(value)?;
+                if let Some(op) = op {
+                    value = apply_binary(op, self.get_var(name)?, value)?;
+                }
+                self.set_var(name, value)?;
+                Ok(value)
+            }
+            Expr::Conditional(condition, then_expr, else_expr) => {
+                if self.eval(condition)? != 0 {
+                    self.eval(then_expr)
+                } else {
+                    self.eval(else_expr)
+                }
+            }
+        }
+    }
+
+    /// Returns the value of a variable, which may be an expression itself.
+    fn get_var(&mut self, name: &str) -> Result<i64> {
+        let value = self.shell.get_parameter(name).unwrap_or_default();
+        if value.trim().is_empty() {
+            return Ok(0);
+        }
+        if let Ok(number) = value.trim().parse::<i64>() {
+            return Ok(number);
+        }
+        evaluate_nested(&value, self.shell, self.depth + 1)
+    }
+
+    fn set_var(&mut self, name: &str, value: i64) -> Result<()> {
+        self.shell.set_var(name, &value.to_string())
+    }
+}
+
+fn apply_binary(op: &str, lhs: i64, rhs: i64) -> Result<i64> {
+    Ok(match op {
+        "+" => lhs.wrapping_add(rhs),
+        "-" => lhs.wrapping_sub(rhs),
+        "*" => lhs.wrapping_mul(rhs),
+        "/" | "%" if rhs == 0 => return Err(anyhow!("division by 0")),
+        "/" => lhs.wrapping_div(rhs),
+        "%" => lhs.wrapping_rem(rhs),
+        "**" if rhs < 0 => return Err(anyhow!("exponent less than 0")),
+        "**" => lhs.wrapping_pow(rhs.min(u32::MAX as i64) as u32),
+        "<<" => lhs.wrapping_shl(rhs as u32),
+        ">>" => lhs.wrapping_shr(rhs as u32),
+        "<" => (lhs < rhs) as i64,
+        ">" => (lhs > rhs) as i64,
+        "<=" => (lhs <= rhs) as i64,
+        ">=" => (lhs >= rhs) as i64,
+        "==" => (lhs == rhs) as i64,
+        "!=" => (lhs != rhs) as i64,
+        "&" => lhs & rhs,
+        "^" => lhs ^ rhs,
+        "|" => lhs | rhs,
+        _ => rhs,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(expression: &str) -> i64 {
+        evaluate(expression, &mut Shell::new()).unwrap()
+    }
+
+    #[test]
+    fn test_precedence() {
+        assert_eq!(eval("1 + 2 * 3"), 7);
+        assert_eq!(eval("(1 + 2) * 3"), 9);
+        assert_eq!(eval("2 ** 3 ** 2"), 512);
+        assert_eq!(eval("-2 ** 2"), 4);
+        assert_eq!(eval("7 / 2 - 7 % 2"), 2);
+        assert_eq!(eval("1 << 4 | 1"), 17);
+        assert_eq!(eval("1 < 2 && 2 < 1 || !0"), 1);
+        assert_eq!(eval("0 ? 1 : 2 ? 3 : 4"), 3);
+        assert_eq!(eval("~0 + -(-3)"), 2);
+        assert_eq!(eval(""), 0);
+    }
+
+    #[test]
+    fn test_literals() {
+        assert_eq!(eval("0x1F + 017"), 46);
+        assert_eq!(eval("2#101 + 36#z + 64#_"), 5 + 35 + 63);
+        assert!(evaluate("09", &mut Shell::new()).is_err());
+        assert!(evaluate("2#102", &mut Shell::new()).is_err());
+    }
+
+    #[test]
+    fn test_variables_and_assignments() {
+        let mut shell = Shell::new();
+        shell.set_var("x", "5").unwrap();
+        shell.set_var("expr", "x * 2").unwrap();
+
+        assert_eq!(evaluate("x++ + ++x", &mut shell).unwrap(), 12);
+        assert_eq!(shell.get_parameter("x").unwrap(), "7");
+        assert_eq!(evaluate("y = x -= 2, y <<= 1", &mut shell).unwrap(), 10);
+        assert_eq!(shell.get_parameter("y").unwrap(), "10");
+        assert_eq!(evaluate("expr + unset_var", &mut shell).unwrap(), 10);
+        assert_eq!(evaluate("0 && z++, z", &mut shell).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut shell = Shell::new();
+        let err = evaluate("1 / 0", &mut shell).unwrap_err();
+        assert_eq!(err.to_string(), "1 / 0: division by 0");
+        assert!(evaluate("5 %= 0", &mut shell).is_err());
+        assert!(evaluate("x %= 0", &mut shell).is_err());
+        assert!(evaluate("2 ** -1", &mut shell).is_err());
+        assert!(evaluate("1 +", &mut shell).is_err());
+        assert!(evaluate("(1", &mut shell).is_err());
+        assert!(evaluate("1 2", &mut shell).is_err());
+    }
+}
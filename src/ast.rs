@@ -0,0 +1,399 @@
+use crate::shell::is_valid_name;
+
+/// Byte range of a syntax node within the parsed input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    /// Returns the span that covers both `self` and `other`.
+    pub fn to(self, other: Span) -> Span {
+        Span::new(self.start.min(other.start), self.end.max(other.end))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WordPart {
+    /// Unquoted text.
+    Literal(String),
+    /// Character preceded by a backslash outside of quotes.
+    Escaped(char),
+    /// Text between single quotes.
+    SingleQuoted(String),
+    /// Parts between double quotes.
+    DoubleQuoted(Vec<WordPart>),
+    /// `$name` or `${name...}`
+    Parameter(ParameterExpansion),
+    /// `$(commands)` or `` `commands` ``
+    CommandSubstitution(CommandList),
+    /// `$((expression))`
+    Arithmetic(Word),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParameterExpansion {
+    pub name: String,
+    pub op: ParameterOp,
+}
+
+/// What to do with the value of a parameter. With `check_null` set (the
+/// forms with a colon) an empty value is treated like an unset one.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParameterOp {
+    /// `$name` or `${name}`
+    Value,
+    /// `${name:-word}`: use `word` if the parameter is unset
+    Default { word: Word, check_null: bool },
+    /// `${name:=word}`: assign `word` if the parameter is unset
+    Assign { word: Word, check_null: bool },
+    /// `${name:?word}`: fail with `word` as message if the parameter is unset
+    Error { word: Word, check_null: bool },
+    /// `${name:+word}`: use `word` if the parameter is set
+    Alternative { word: Word, check_null: bool },
+    /// `${#name}`: number of characters of the value
+    Length,
+    /// `${name#pattern}` or with `longest` set `${name##pattern}`
+    RemovePrefix { pattern: Word, longest: bool },
+    /// `${name%pattern}` or with `longest` set `${name%%pattern}`
+    RemoveSuffix { pattern: Word, longest: bool },
+    /// `${name/pattern/replacement}` or with `all` set
+    /// `${name//pattern/replacement}`
+    Replace {
+        pattern: Word,
+        replacement: Word,
+        all: bool,
+    },
+    /// `${name:offset}` or `${name:offset:length}`
+    Substring { offset: Word, length: Option<Word> },
+    /// `${name^pattern}` and `${name,pattern}` convert the first character,
+    /// `${name^^pattern}` and `${name,,pattern}` all characters matching the
+    /// pattern to upper or lower case. An empty pattern matches everything.
+    CaseModification {
+        upper: bool,
+        all: bool,
+        pattern: Word,
+    },
+}
+
+impl WordPart {
+    fn push_unquoted(&self, text: &mut String) {
+        match self {
+            WordPart::Literal(s) | WordPart::SingleQuoted(s) => text.push_str(s),
+            WordPart::Escaped(ch) => text.push(*ch),
+            WordPart::DoubleQuoted(parts) => {
+                for part in parts {
+                    part.push_unquoted(text);
+                }
+            }
+            WordPart::Parameter(parameter) => {
+                text.push('$');
+                text.push_str(&parameter.name);
+            }
+            WordPart::CommandSubstitution(_) => text.push_str("$(...)"),
+            WordPart::Arithmetic(expression) => {
+                text.push_str("$((");
+                text.push_str(&expression.unquoted());
+                text.push_str("))");
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Word {
+    pub parts: Vec<WordPart>,
+    pub span: Span,
+}
+
+impl Word {
+    /// Returns the text of the word after quote removal, without performing
+    /// any expansions.
+    pub fn unquoted(&self) -> String {
+        let mut text = String::new();
+        for part in &self.parts {
+            part.push_unquoted(&mut text);
+        }
+        text
+    }
+
+    /// Returns whether the word contains a command substitution outside of
+    /// parameter expansions.
+    pub fn has_command_substitution(&self) -> bool {
+        fn contains(parts: &[WordPart]) -> bool {
+            parts.iter().any(|part| match part {
+                WordPart::CommandSubstitution(_) => true,
+                WordPart::DoubleQuoted(parts) => contains(parts),
+                _ => false,
+            })
+        }
+        contains(&self.parts)
+    }
+
+    /// Returns the variable name if the word is an assignment like
+    /// `name=value`.
+    pub fn assignment_name(&self) -> Option<&str> {
+        match self.parts.first() {
+            Some(WordPart::Literal(text)) => text
+                .split_once('=')
+                .map(|(name, _)| name)
+                .filter(|name| is_valid_name(name)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RedirectOp {
+    /// `>`: truncate or create the target file
+    Output,
+    /// `>>`: append to the target file
+    Append,
+    /// `<`: read from the target file
+    Input,
+    /// `<>`: open the target file for reading and writing
+    ReadWrite,
+    /// `>&`: duplicate (or with `-` close) an output descriptor
+    DupOutput,
+    /// `<&`: duplicate (or with `-` close) an input descriptor
+    DupInput,
+    /// `&>`: redirect stdout and stderr to the target file
+    OutputAll,
+    /// `&>>`: append stdout and stderr to the target file
+    AppendAll,
+    /// `<<` or `<<-`: the target is the body of a here-document
+    HereDoc { strip_tabs: bool },
+    /// `<<<`: the target is the content followed by a newline
+    HereString,
+}
+
+impl RedirectOp {
+    /// Descriptor that is redirected if the operator has no explicit number.
+    pub fn default_fd(&self) -> i32 {
+        match self {
+            RedirectOp::Output
+            | RedirectOp::Append
+            | RedirectOp::DupOutput
+            | RedirectOp::OutputAll
+            | RedirectOp::AppendAll => 1,
+            RedirectOp::Input
+            | RedirectOp::ReadWrite
+            | RedirectOp::DupInput
+            | RedirectOp::HereDoc { .. }
+            | RedirectOp::HereString => 0,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RedirectOp::Output => ">",
+            RedirectOp::Append => ">>",
+            RedirectOp::Input => "<",
+            RedirectOp::ReadWrite => "<>",
+            RedirectOp::DupOutput => ">&",
+            RedirectOp::DupInput => "<&",
+            RedirectOp::OutputAll => "&>",
+            RedirectOp::AppendAll => "&>>",
+            RedirectOp::HereDoc { strip_tabs: false } => "<<",
+            RedirectOp::HereDoc { strip_tabs: true } => "<<-",
+            RedirectOp::HereString => "<<<",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Redirect {
+    pub fd: i32,
+    pub op: RedirectOp,
+    pub target: Word,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimpleCommand {
+    /// Leading `name=value` words. They set shell variables if there are no
+    /// words and are only passed to the command otherwise.
+    pub assignments: Vec<Word>,
+    pub words: Vec<Word>,
+    pub redirects: Vec<Redirect>,
+    pub span: Span,
+}
+
+/// `((expression))`: succeeds if the expression evaluates to non-zero.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArithmeticCommand {
+    pub expression: Word,
+    pub redirects: Vec<Redirect>,
+    pub span: Span,
+}
+
+/// `if list; then list; [elif list; then list;]... [else list;] fi`
+#[derive(Debug, Clone, PartialEq)]
+pub struct IfClause {
+    /// Conditions with the commands that run if they succeed.
+    pub branches: Vec<(CommandList, CommandList)>,
+    pub else_branch: Option<CommandList>,
+    pub redirects: Vec<Redirect>,
+    pub span: Span,
+}
+
+/// `case word in [(]pattern[|pattern]...) list ;; ... esac`
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaseClause {
+    pub word: Word,
+    pub items: Vec<CaseItem>,
+    pub redirects: Vec<Redirect>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaseItem {
+    pub patterns: Vec<Word>,
+    pub body: CommandList,
+    pub terminator: CaseTerminator,
+    /// From the patterns through the terminator.
+    pub span: Span,
+}
+
+/// What happens after the body of a matching case item has run.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CaseTerminator {
+    /// `;;`: the case command is done
+    Break,
+    /// `;&`: the body of the next item runs as well
+    FallThrough,
+    /// `;;&`: the patterns of the following items are tested as well
+    Continue,
+}
+
+/// `while list; do list; done` or, with `until` set, `until list; do list;
+/// done`, which runs the body as long as the condition fails.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WhileLoop {
+    pub condition: CommandList,
+    pub body: CommandList,
+    pub until: bool,
+    pub redirects: Vec<Redirect>,
+    pub span: Span,
+}
+
+/// `for name [in word...]; do list; done`. Without `in` the loop runs over
+/// the positional parameters.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ForLoop {
+    pub name: String,
+    pub words: Option<Vec<Word>>,
+    pub body: CommandList,
+    pub redirects: Vec<Redirect>,
+    pub span: Span,
+}
+
+/// `for ((init; condition; update)); do list; done`. An empty condition is
+/// always true.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArithmeticForLoop {
+    pub init: Word,
+    pub condition: Word,
+    pub update: Word,
+    pub body: CommandList,
+    pub redirects: Vec<Redirect>,
+    pub span: Span,
+}
+
+/// `( list )`: runs the commands in a subshell.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Subshell {
+    pub body: CommandList,
+    pub redirects: Vec<Redirect>,
+    pub span: Span,
+}
+
+/// `{ list; }`: runs the commands in the current shell, e.g. to redirect
+/// them together.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Group {
+    pub body: CommandList,
+    pub redirects: Vec<Redirect>,
+    pub span: Span,
+}
+
+/// `name() { list; }` or `function name { list; }`. The body can be any
+/// compound command; its redirections apply whenever the function is called.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionDefinition {
+    pub name: String,
+    pub body: Box<Command>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Simple(SimpleCommand),
+    Arithmetic(ArithmeticCommand),
+    If(IfClause),
+    Case(CaseClause),
+    While(WhileLoop),
+    For(ForLoop),
+    ArithmeticFor(ArithmeticForLoop),
+    Subshell(Subshell),
+    Group(Group),
+    FunctionDefinition(FunctionDefinition),
+}
+
+impl Command {
+    pub fn span(&self) -> Span {
+        match self {
+            Command::Simple(simple) => simple.span,
+            Command::Arithmetic(arithmetic) => arithmetic.span,
+            Command::If(if_clause) => if_clause.span,
+            Command::Case(case_clause) => case_clause.span,
+            Command::While(while_loop) => while_loop.span,
+            Command::For(for_loop) => for_loop.span,
+            Command::ArithmeticFor(for_loop) => for_loop.span,
+            Command::Subshell(subshell) => subshell.span,
+            Command::Group(group) => group.span,
+            Command::FunctionDefinition(function) => function.span,
+        }
+    }
+
+    pub fn redirects_mut(&mut self) -> &mut Vec<Redirect> {
+        match self {
+            Command::Simple(simple) => &mut simple.redirects,
+            Command::Arithmetic(arithmetic) => &mut arithmetic.redirects,
+            Command::If(if_clause) => &mut if_clause.redirects,
+            Command::Case(case_clause) => &mut case_clause.redirects,
+            Command::While(while_loop) => &mut while_loop.redirects,
+            Command::For(for_loop) => &mut for_loop.redirects,
+            Command::ArithmeticFor(for_loop) => &mut for_loop.redirects,
+            Command::Subshell(subshell) => &mut subshell.redirects,
+            Command::Group(group) => &mut group.redirects,
+            Command::FunctionDefinition(function) => function.body.redirects_mut(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pipeline {
+    pub commands: Vec<Command>,
+    pub span: Span,
+}
+
+/// Operator that connects a pipeline to the one before it in a command list.
+/// The first pipeline of a list is always connected with `Seq`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ListOperator {
+    Seq,
+    And,
+    Or,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommandList {
+    pub items: Vec<(ListOperator, Pipeline)>,
+    pub span: Span,
+}
@@ -0,0 +1,241 @@
+use crate::ast::{Word, WordPart};
+
+/// Part of a word during brace expansion. Only unquoted characters can form
+/// a brace expression; all other parts are kept as they are.
+#[derive(Debug, Clone, PartialEq)]
+enum Unit {
+    Char(char),
+    Part(WordPart),
+}
+
+/// Expands brace expressions like `a{b,c}` and `{1..10..2}` into one word
+/// per alternative. Words without brace expressions are returned unchanged.
+pub fn expand_braces(word: &Word) -> Vec<Word> {
+    let mut units = vec![];
+    for part in &word.parts {
+        match part {
+            WordPart::Literal(text) => units.extend(text.chars().map(Unit::Char)),
+            part => units.push(Unit::Part(part.clone())),
+        }
+    }
+
+    expand_units(units)
+        .into_iter()
+        .map(|units| Word {
+            parts: to_parts(units),
+            span: word.span,
+        })
+        .collect()
+}
+
+fn expand_units(units: Vec<Unit>) -> Vec<Vec<Unit>> {
+    for start in 0..units.len() {
+        if units[start] != Unit::Char('{') {
+            continue;
+        }
+        let Some((end, commas)) = find_closing_brace(&units, start) else {
+            continue;
+        };
+        let alternatives: Vec<Vec<Unit>> = if !commas.is_empty() {
+            let mut bounds = vec![start];
+            bounds.extend(commas);
+            bounds.push(end);
+            bounds
+                .windows(2)
+                .map(|bound| units[bound[0] + 1..bound[1]].to_vec())
+                .collect()
+        } else {
+            match expand_sequence(&units[start + 1..end]) {
+                Some(items) => items
+                    .into_iter()
+                    .map(|item| item.chars().map(Unit::Char).collect())
+                    .collect(),
+                None => continue,
+            }
+        };
+
+        let mut words = vec![];
+        for alternative in alternatives {
+            let mut expanded = units[..start].to_vec();
+            expanded.extend(alternative);
+            expanded.extend_from_slice(&units[end + 1..]);
+            words.extend(expand_units(expanded));
+        }
+        return words;
+    }
+
+    vec![units]
+}
+
+/// Returns the position of the `}` matching the `{` at `start` and the
+/// positions of the commas that separate its alternatives.
+fn find_closing_brace(units: &[Unit], start: usize) -> Option<(usize, Vec<usize>)> {
+    let mut depth = 0;
+    let mut commas = vec![];
+    for (pos, unit) in units.iter().enumerate().skip(start + 1) {
+        match unit {
+            Unit::Char('{') => depth += 1,
+            Unit::Char('}') if depth == 0 => return Some((pos, commas)),
+            Unit::Char('}') => depth -= 1,
+            Unit::Char(',') if depth == 0 => commas.push(pos),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Expands a sequence expression like `1..10`, `01..20..2` or `a..e`.
+fn expand_sequence(units: &[Unit]) -> Option<Vec<String>> {
+    let mut text = String::new();
+    for unit in units {
+        match unit {
+            Unit::Char(ch) => text.push(*ch),
+            Unit::Part(_) => return None,
+        }
+    }
+    let fields: Vec<&str> = text.split("..").collect();
+    let (first, last, step) = match fields.as_slice() {
+        [first, last] => (*first, *last, 1),
+        [first, last, step] => (
+            *first,
+            *last,
+            step.parse::<i64>().ok()?.unsigned_abs().max(1),
+        ),
+        _ => return None,
+    };
+
+    if let (Ok(start), Ok(end)) = (first.parse::<i64>(), last.parse::<i64>()) {
+        let is_padded =
+            |number: &str| number.trim_start_matches('-').starts_with('0') && number.len() > 1;
+        let width = if is_padded(first) || is_padded(last) {
+            first.len().max(last.len())
+        } else {
+            0
+        };
+        return Some(
+            range(start, end, step)
+                .into_iter()
+                .map(|number| format!("{number:0width$}"))
+                .collect(),
+        );
+    }
+
+    let (mut first_chars, mut last_chars) = (first.chars(), last.chars());
+    match (
+        first_chars.next(),
+        first_chars.next(),
+        last_chars.next(),
+        last_chars.next(),
+    ) {
+        (Some(start), None, Some(end), None)
+            if start.is_ascii_alphabetic() && end.is_ascii_alphabetic() =>
+        {
+            Some(
+                range(start as i64, end as i64, step)
+                    .into_iter()
+                    .filter_map(|code| char::from_u32(code as u32))
+                    .map(String::from)
+                    .collect(),
+            )
+        }
+        _ => None,
+    }
+}
+
+/// Returns the numbers from `start` to `end` in steps of `step`, counting
+/// down if `end` is less than `start`.
+fn range(start: i64, end: i64, step: u64) -> Vec<i64> {
+    let mut numbers = vec![];
+    let mut number = start;
+    loop {
+        numbers.push(number);
+        let next = if start <= end {
+            number.checked_add_unsigned(step)
+        } else {
+            number.checked_sub_unsigned(step)
+        };
+        match next {
+            Some(next) if (start <= end && next <= end) || (start > end && next >= end) => {
+                number = next
+            }
+            _ => break,
+        }
+    }
+    numbers
+}
+
+fn to_parts(units: Vec<Unit>) -> Vec<WordPart> {
+    let mut parts = vec![];
+    let mut literal = String::new();
+    for unit in units {
+        match unit {
+            Unit::Char(ch) => literal.push(ch),
+            Unit::Part(part) => {
+                if !literal.is_empty() {
+                    parts.push(WordPart::Literal(std::mem::take(&mut literal)));
+                }
+                parts.push(part);
+            }
+        }
+    }
+    if !literal.is_empty() {
+        parts.push(WordPart::Literal(literal));
+    }
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Span;
+
+    fn expand(parts: Vec<WordPart>) -> Vec<String> {
+        let word = Word {
+            parts,
+            span: Span::default(),
+        };
+        expand_braces(&word).iter().map(Word::unquoted).collect()
+    }
+
+    fn expand_literal(text: &str) -> Vec<String> {
+        expand(vec![WordPart::Literal(text.to_string())])
+    }
+
+    #[test]
+    fn test_alternatives() {
+        assert_eq!(expand_literal("src/{bin,lib}"), vec!["src/bin", "src/lib"]);
+        assert_eq!(expand_literal("{a,b{1,2}}c"), vec!["ac", "b1c", "b2c"]);
+        assert_eq!(expand_literal("x{a,}y"), vec!["xay", "xy"]);
+        assert_eq!(expand_literal("{a}{}"), vec!["{a}{}"]);
+        assert_eq!(expand_literal("{a{b,c}"), vec!["{ab", "{ac"]);
+        assert_eq!(expand_literal("{a,b}{1,2}"), vec!["a1", "a2", "b1", "b2"]);
+    }
+
+    #[test]
+    fn test_sequences() {
+        assert_eq!(expand_literal("{1..10..3}"), vec!["1", "4", "7", "10"]);
+        assert_eq!(expand_literal("{3..1}"), vec!["3", "2", "1"]);
+        assert_eq!(expand_literal("{1..3..-1}"), vec!["1", "2", "3"]);
+        assert_eq!(expand_literal("{05..-3..4}"), vec!["05", "01", "-3"]);
+        assert_eq!(expand_literal("{-01..2}"), vec!["-01", "000", "001", "002"]);
+        assert_eq!(expand_literal("{a..e..2}"), vec!["a", "c", "e"]);
+        assert_eq!(expand_literal("{z..x}"), vec!["z", "y", "x"]);
+        assert_eq!(expand_literal("{1..a}"), vec!["{1..a}"]);
+    }
+
+    #[test]
+    fn test_quoted_parts() {
+        let parts = vec![
+            WordPart::Literal("{a,".to_string()),
+            WordPart::DoubleQuoted(vec![WordPart::Literal("b c".to_string())]),
+            WordPart::Literal("}".to_string()),
+        ];
+        assert_eq!(expand(parts), vec!["a", "b c"]);
+
+        let parts = vec![
+            WordPart::Escaped('{'),
+            WordPart::Literal("a,b}".to_string()),
+        ];
+        assert_eq!(expand(parts), vec!["{a,b}"]);
+    }
+}
@@ -1,24 +1,65 @@
-use crate::arg_parse::CommandList;
-use crate::redirect::{FileOpenMode, Output, RedirectionInfo};
+use crate::arith;
+use crate::arg_parse::RESERVED_WORDS;
+use crate::ast::{
+    ArithmeticCommand, ArithmeticForLoop, CaseClause, CaseTerminator, Command as AstCommand, CommandList, ForLoop,
+    FunctionDefinition, Group, IfClause, ListOperator, Pipeline, Redirect, RedirectOp, SimpleCommand, Subshell,
+    WhileLoop, Word,
+};
+use crate::expand::{expand_assignment, expand_pattern, expand_word, expand_words, split_fields};
+use crate::history::History;
+use crate::redirect::{pipe, FileOpenMode, Output, RedirectionInfo};
 use anyhow::{anyhow, Result};
 use std::cmp::PartialEq;
 use std::collections::HashSet;
 use std::env;
-use std::process::{Child, ChildStdout, Command, Output as ProcessOutput, Stdio};
-use std::io::Write;
-use crate::history::History;
+use std::fs;
+use std::io::{self, Read};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::Path;
+use std::process::{Command, ExitStatus};
+use std::thread;
+use crate::shell::{is_valid_name, Shell, Variable};
+use crate::tilde;
 
 #[derive(Debug, PartialEq)]
 pub enum ExecResult {
     Exit(i32),
     Continue,
+    /// `break n`: leave the `n` innermost loops
+    Break(usize),
+    /// `continue n`: go on with the next iteration of the `n`-th loop
+    NextIteration(usize),
+    /// `return n`: leave the function that is executed with status `n`
+    Return(i32),
 }
 
-#[derive(Debug)]
-pub enum CommandOutput {
-    Str(String),
-    Out(ProcessOutput),
-    ChildOut(ChildStdout),
+/// How a loop goes on after its condition or body has run.
+enum LoopStep {
+    Next,
+    Stop,
+    /// Stop and pass the result on to the enclosing commands.
+    Return(ExecResult),
+}
+
+impl LoopStep {
+    fn from(exec_result: ExecResult) -> Self {
+        match exec_result {
+            ExecResult::Continue | ExecResult::NextIteration(1) => LoopStep::Next,
+            ExecResult::Break(1) => LoopStep::Stop,
+            ExecResult::Break(count) => LoopStep::Return(ExecResult::Break(count - 1)),
+            ExecResult::NextIteration(count) => LoopStep::Return(ExecResult::NextIteration(count - 1)),
+            exec_result @ (ExecResult::Exit(_) | ExecResult::Return(_)) => LoopStep::Return(exec_result),
+        }
+    }
+
+    /// Like `from` for the result of a loop body. A body whose output broke
+    /// stops the loop, since nobody reads what it writes anymore.
+    fn after_body(exec_result: ExecResult, status: i32) -> Self {
+        match exec_result {
+            ExecResult::Continue if status == BROKEN_PIPE_STATUS => LoopStep::Stop,
+            exec_result => LoopStep::from(exec_result),
+        }
+    }
 }
 
 pub fn get_builtin_commands() -> HashSet<String> {
@@ -29,139 +70,630 @@ pub fn get_builtin_commands() -> HashSet<String> {
         "pwd".to_string(),
         "type".to_string(),
         "history".to_string(),
+        "let".to_string(),
+        "shopt".to_string(),
+        "read".to_string(),
+        "break".to_string(),
+        "continue".to_string(),
+        "return".to_string(),
+        "local".to_string(),
+        "alias".to_string(),
+        "unalias".to_string(),
+        "declare".to_string(),
+        "typeset".to_string(),
+        "export".to_string(),
+        "readonly".to_string(),
+        "unset".to_string(),
     ])
 }
 
-pub fn run_commands(commands: &CommandList, history: &mut History) -> Result<ExecResult> {
-    let mut prev_output: Option<CommandOutput> = None;
-    let mut exec_result = ExecResult::Continue;
+/// POSIX special builtins: assignments in front of them are not undone when
+/// they finish.
+const SPECIAL_BUILTINS: &[&str] = &[
+    "break", ":", "continue", ".", "eval", "exec", "exit", "export", "readonly", "return", "set", "shift", "times",
+    "trap", "unset",
+];
 
-    let last_idx = commands.len() - 1;
+#[derive(Debug, thiserror::Error)]
+#[error("{0}: not found")]
+pub struct CommandNotFound(String);
 
-    for (idx, (command, args)) in commands.iter().enumerate() {
-        let (result, output) = run_command(
-            command,
-            args,
-            prev_output,
-            last_idx > 0,
-            idx == last_idx,
-            history,
-        )?;
-        exec_result = result;
-        prev_output = Some(output);
-        if exec_result != ExecResult::Continue {
-            break;
+/// Status of a command that wrote into a pipe without reader: 128 + `SIGPIPE`.
+const BROKEN_PIPE_STATUS: i32 = 128 + libc::SIGPIPE;
+
+fn is_broken_pipe(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<io::Error>()
+        .is_some_and(|err| err.kind() == io::ErrorKind::BrokenPipe)
+}
+
+/// Runs a command list with the file descriptors of `io`, e.g. the shell's
+/// standard streams or the pipe of a command substitution.
+pub fn run_commands(
+    commands: &CommandList,
+    io: &RedirectionInfo,
+    shell: &mut Shell,
+) -> Result<ExecResult> {
+    let mut status = 0;
+
+    for (operator, pipeline) in &commands.items {
+        let skip = match operator {
+            ListOperator::Seq => false,
+            ListOperator::And => status != 0,
+            ListOperator::Or => status == 0,
+        };
+        if skip {
+            continue;
         }
+        match run_pipeline(pipeline, io, shell) {
+            Ok((ExecResult::Continue, pipeline_status)) => status = pipeline_status,
+            Ok((exec_result, pipeline_status)) => {
+                shell.last_status = pipeline_status;
+                return Ok(exec_result);
+            }
+            Err(err) => status = report_error(&err),
+        }
+        shell.last_status = status;
+    }
+
+    Ok(ExecResult::Continue)
+}
+
+/// Errors outside of any command, like a failed pipe or redirection, go to
+/// the shell's own stderr and give status 1.
+fn report_error(err: &anyhow::Error) -> i32 {
+    eprintln!("{}", err);
+    1
+}
+
+/// Runs a lone command directly. Otherwise every stage runs concurrently in
+/// a scoped thread on its own copy of the shell, connected to its neighbours
+/// by OS pipes set as descriptors 0 and 1 of its `RedirectionInfo`; builtins
+/// and external processes write through the same `Output` descriptors. The
+/// status is the one of the last stage.
+fn run_pipeline(
+    pipeline: &Pipeline,
+    base_io: &RedirectionInfo,
+    shell: &mut Shell,
+) -> Result<(ExecResult, i32)> {
+    if let [command] = pipeline.commands.as_slice() {
+        return run_command(command, base_io.clone(), shell);
     }
 
-    Ok(exec_result)
+    let last_idx = pipeline.commands.len() - 1;
+    let mut stage_io = vec![];
+    let mut stdin = None;
+
+    for idx in 0..=last_idx {
+        let mut io = base_io.clone();
+        if let Some(reader) = stdin.take() {
+            io.set(0, reader);
+        }
+        if idx < last_idx {
+            let (reader, writer) = pipe()?;
+            io.set(1, writer);
+            stdin = Some(reader);
+        }
+        stage_io.push(io);
+    }
+
+    // As in a subshell, every stage works on its own copy of the shell state
+    let statuses: Vec<i32> = thread::scope(|scope| {
+        let stages: Vec<_> = pipeline
+            .commands
+            .iter()
+            .zip(stage_io)
+            .map(|(command, io)| {
+                let mut shell = shell.clone();
+                scope.spawn(move || {
+                    match run_command(command, io, &mut shell) {
+                        Ok((ExecResult::Exit(code), _)) => code,
+                        Ok((_, status)) => status,
+                        Err(err) => report_error(&err),
+                    }
+                })
+            })
+            .collect();
+        stages
+            .into_iter()
+            .map(|stage| stage.join().unwrap_or(1))
+            .collect()
+    });
+
+    Ok((ExecResult::Continue, statuses[last_idx]))
+}
+
+/// Runs commands like a subshell: on a copy of the shell state, including
+/// its working directory, in a thread. Loops of the shell cannot be left
+/// from inside. Returns the exit status of the commands.
+pub fn run_subshell(commands: &CommandList, io: &RedirectionInfo, shell: &Shell) -> i32 {
+    let mut subshell = shell.clone();
+    subshell.loop_depth = 0;
+    thread::scope(|scope| {
+        scope
+            .spawn(|| {
+                match run_commands(commands, io, &mut subshell) {
+                    Ok(ExecResult::Exit(code)) => code,
+                    Ok(_) => subshell.last_status,
+                    Err(err) => report_error(&err),
+                }
+            })
+            .join()
+            .unwrap_or(1)
+    })
 }
 
 fn run_command(
-    command: &str,
-    args: &Vec<String>,
-    prev_output: Option<CommandOutput>,
-    is_part_of_pipe: bool,
-    is_last_in_pipe: bool,
-    history: &mut History,
-) -> Result<(ExecResult, CommandOutput)> {
-    let (args, redirection_info) = check_for_redirections(args);
+    command: &AstCommand,
+    io: RedirectionInfo,
+    shell: &mut Shell,
+) -> Result<(ExecResult, i32)> {
+    match command {
+        AstCommand::Simple(simple) => run_simple_command(simple, io, shell),
+        AstCommand::Arithmetic(arithmetic) => run_arithmetic_command(arithmetic, io, shell),
+        AstCommand::If(if_clause) => run_if_clause(if_clause, io, shell),
+        AstCommand::Case(case_clause) => run_case_clause(case_clause, io, shell),
+        AstCommand::While(while_loop) => in_loop(shell, |shell| run_while_loop(while_loop, io, shell)),
+        AstCommand::For(for_loop) => in_loop(shell, |shell| run_for_loop(for_loop, io, shell)),
+        AstCommand::ArithmeticFor(for_loop) => in_loop(shell, |shell| run_arithmetic_for_loop(for_loop, io, shell)),
+        AstCommand::Subshell(subshell) => run_subshell_command(subshell, io, shell),
+        AstCommand::Group(group) => run_group(group, io, shell),
+        AstCommand::FunctionDefinition(function) => {
+            shell.define_function(function.clone());
+            Ok((ExecResult::Continue, 0))
+        }
+    }
+}
+
+/// Runs the list of a compound command and returns the status of its last
+/// pipeline, or 0 if it is empty.
+fn run_compound_list(
+    commands: &CommandList,
+    io: &RedirectionInfo,
+    shell: &mut Shell,
+) -> Result<(ExecResult, i32)> {
+    if commands.items.is_empty() {
+        return Ok((ExecResult::Continue, 0));
+    }
+    let exec_result = run_commands(commands, io, shell)?;
+    Ok((exec_result, shell.last_status))
+}
+
+fn run_subshell_command(
+    subshell: &Subshell,
+    mut io: RedirectionInfo,
+    shell: &mut Shell,
+) -> Result<(ExecResult, i32)> {
+    for redirect in &subshell.redirects {
+        apply_redirect(&mut io, redirect, shell)?;
+    }
+    Ok((ExecResult::Continue, run_subshell(&subshell.body, &io, shell)))
+}
+
+fn run_group(
+    group: &Group,
+    mut io: RedirectionInfo,
+    shell: &mut Shell,
+) -> Result<(ExecResult, i32)> {
+    for redirect in &group.redirects {
+        apply_redirect(&mut io, redirect, shell)?;
+    }
+    run_compound_list(&group.body, &io, shell)
+}
+
+fn run_if_clause(
+    if_clause: &IfClause,
+    mut io: RedirectionInfo,
+    shell: &mut Shell,
+) -> Result<(ExecResult, i32)> {
+    for redirect in &if_clause.redirects {
+        apply_redirect(&mut io, redirect, shell)?;
+    }
+    for (condition, body) in &if_clause.branches {
+        match run_compound_list(condition, &io, shell)? {
+            (ExecResult::Continue, 0) => return run_compound_list(body, &io, shell),
+            (ExecResult::Continue, _) => {}
+            (exec_result, status) => return Ok((exec_result, status)),
+        }
+    }
+    match &if_clause.else_branch {
+        Some(body) => run_compound_list(body, &io, shell),
+        None => Ok((ExecResult::Continue, 0)),
+    }
+}
+
+fn run_case_clause(
+    case_clause: &CaseClause,
+    mut io: RedirectionInfo,
+    shell: &mut Shell,
+) -> Result<(ExecResult, i32)> {
+    for redirect in &case_clause.redirects {
+        apply_redirect(&mut io, redirect, shell)?;
+    }
+    let word = expand_word(&tilde::expand_tilde(&case_clause.word, shell), shell)?;
+
+    let mut status = 0;
+    let mut fall_through = false;
+    for item in &case_clause.items {
+        let mut matches = fall_through;
+        for pattern in &item.patterns {
+            if matches {
+                break;
+            }
+            matches = expand_pattern(&tilde::expand_tilde(pattern, shell), shell)?.matches(&word);
+        }
+        if !matches {
+            continue;
+        }
+
+        let exec_result;
+        (exec_result, status) = run_compound_list(&item.body, &io, shell)?;
+        if exec_result != ExecResult::Continue {
+            return Ok((exec_result, status));
+        }
+        match item.terminator {
+            CaseTerminator::Break => break,
+            CaseTerminator::FallThrough => fall_through = true,
+            CaseTerminator::Continue => fall_through = false,
+        }
+    }
 
-    let mut output = redirection_info.get_output();
-    output.open()?;
+    Ok((ExecResult::Continue, status))
+}
 
-    let mut error_output = redirection_info.get_error_output();
-    error_output.open()?;
+/// Runs a loop and keeps track of the loop nesting for `break` and
+/// `continue`.
+fn in_loop(
+    shell: &mut Shell,
+    run: impl FnOnce(&mut Shell) -> Result<(ExecResult, i32)>,
+) -> Result<(ExecResult, i32)> {
+    shell.loop_depth += 1;
+    let result = run(shell);
+    shell.loop_depth -= 1;
+    result
+}
 
-    let mut out_str = String::new();
-    let mut command_output_opt: Option<CommandOutput> = None;
+fn run_while_loop(
+    while_loop: &WhileLoop,
+    mut io: RedirectionInfo,
+    shell: &mut Shell,
+) -> Result<(ExecResult, i32)> {
+    for redirect in &while_loop.redirects {
+        apply_redirect(&mut io, redirect, shell)?;
+    }
 
-    let piped = is_part_of_pipe && !is_last_in_pipe;
+    let mut status = 0;
+    loop {
+        let (exec_result, condition_status) = run_compound_list(&while_loop.condition, &io, shell)?;
+        match LoopStep::from(exec_result) {
+            LoopStep::Next if (condition_status == 0) != while_loop.until => {}
+            LoopStep::Next => break,
+            LoopStep::Stop => return Ok((ExecResult::Continue, 0)),
+            LoopStep::Return(exec_result) => return Ok((exec_result, condition_status)),
+        }
 
+        let exec_result;
+        (exec_result, status) = run_compound_list(&while_loop.body, &io, shell)?;
+        match LoopStep::after_body(exec_result, status) {
+            LoopStep::Next => {}
+            LoopStep::Stop => break,
+            LoopStep::Return(exec_result) => return Ok((exec_result, status)),
+        }
+    }
+
+    Ok((ExecResult::Continue, status))
+}
+
+fn run_for_loop(
+    for_loop: &ForLoop,
+    mut io: RedirectionInfo,
+    shell: &mut Shell,
+) -> Result<(ExecResult, i32)> {
+    for redirect in &for_loop.redirects {
+        apply_redirect(&mut io, redirect, shell)?;
+    }
+    let values = match &for_loop.words {
+        Some(words) => expand_words(words, shell)?,
+        None => shell.positional.clone(),
+    };
+
+    let mut status = 0;
+    for value in values {
+        shell.set_var(&for_loop.name, &value)?;
+        let exec_result;
+        (exec_result, status) = run_compound_list(&for_loop.body, &io, shell)?;
+        match LoopStep::after_body(exec_result, status) {
+            LoopStep::Next => {}
+            LoopStep::Stop => break,
+            LoopStep::Return(exec_result) => return Ok((exec_result, status)),
+        }
+    }
+
+    Ok((ExecResult::Continue, status))
+}
+
+fn run_arithmetic_for_loop(
+    for_loop: &ArithmeticForLoop,
+    mut io: RedirectionInfo,
+    shell: &mut Shell,
+) -> Result<(ExecResult, i32)> {
+    for redirect in &for_loop.redirects {
+        apply_redirect(&mut io, redirect, shell)?;
+    }
+
+    let mut status = 0;
+    evaluate_optional(&for_loop.init, shell)?;
+    while evaluate_optional(&for_loop.condition, shell)? != Some(0) {
+        let exec_result;
+        (exec_result, status) = run_compound_list(&for_loop.body, &io, shell)?;
+        match LoopStep::after_body(exec_result, status) {
+            LoopStep::Next => {}
+            LoopStep::Stop => break,
+            LoopStep::Return(exec_result) => return Ok((exec_result, status)),
+        }
+        evaluate_optional(&for_loop.update, shell)?;
+    }
+
+    Ok((ExecResult::Continue, status))
+}
+
+/// Evaluates an arithmetic expression of `for ((...))`, which may be empty.
+fn evaluate_optional(expression: &Word, shell: &mut Shell) -> Result<Option<i64>> {
+    let expression = expand_word(expression, shell)?;
+    if expression.trim().is_empty() {
+        return Ok(None);
+    }
+    arith::evaluate(&expression, shell).map(Some)
+}
+
+fn run_simple_command(
+    simple: &SimpleCommand,
+    mut io: RedirectionInfo,
+    shell: &mut Shell,
+) -> Result<(ExecResult, i32)> {
+    // Words are expanded first, so a failed expansion leaves files alone
+    let args = expand_words(&simple.words, shell)?;
+    for redirect in &simple.redirects {
+        apply_redirect(&mut io, redirect, shell)?;
+    }
+
+    if args.is_empty() {
+        let status = assign_variables(&simple.assignments, shell).unwrap_or_else(|err| report_command_error(&io, &err));
+        return Ok((ExecResult::Continue, status));
+    }
+
+    let mut env = vec![];
+    for word in &simple.assignments {
+        env.push(expand_assignment(word, shell)?);
+    }
+    let is_function = shell.function(&args[0]).is_some();
+    let is_builtin = get_builtin_commands().contains(&args[0]);
+    let result = if env.is_empty() || !(is_function || is_builtin) {
+        run_args(args, &env, &io, shell)
+    } else if is_builtin && SPECIAL_BUILTINS.contains(&args[0].as_str()) && !is_function {
+        env.iter()
+            .try_for_each(|(name, value)| shell.set_var(name, value))
+            .and_then(|()| run_args(args, &[], &io, shell))
+    } else {
+        with_temporary_vars(&env, shell, |shell| run_args(args, &[], &io, shell))
+    };
+    result.or_else(|err| Ok((ExecResult::Continue, report_command_error(&io, &err))))
+}
+
+/// Assigns the assignments of a command without words to shell variables.
+/// The status is the one of the last command substitution, if any.
+fn assign_variables(assignments: &[Word], shell: &mut Shell) -> Result<i32> {
+    let mut status = 0;
+    for word in assignments {
+        let (name, value) = expand_assignment(word, shell)?;
+        if word.has_command_substitution() {
+            status = shell.last_status;
+        }
+        shell.set_var(&name, &value)?;
+    }
+    Ok(status)
+}
+
+/// Runs a builtin or function with the assignments in front of it as
+/// exported variables, which are restored afterwards.
+fn with_temporary_vars(
+    env: &[(String, String)],
+    shell: &mut Shell,
+    run: impl FnOnce(&mut Shell) -> Result<(ExecResult, i32)>,
+) -> Result<(ExecResult, i32)> {
+    let saved: Vec<_> = env
+        .iter()
+        .map(|(name, _)| (name, shell.variable(name).cloned()))
+        .collect();
+    let result = env
+        .iter()
+        .try_for_each(|(name, value)| {
+            shell.set_var(name, value)?;
+            shell.set_attribute(name, 'x', true)
+        })
+        .and_then(|()| run(shell));
+    for (name, variable) in saved.into_iter().rev() {
+        shell.restore_var(name, variable);
+    }
+    result
+}
+
+fn run_arithmetic_command(
+    arithmetic: &ArithmeticCommand,
+    mut io: RedirectionInfo,
+    shell: &mut Shell,
+) -> Result<(ExecResult, i32)> {
+    for redirect in &arithmetic.redirects {
+        apply_redirect(&mut io, redirect, shell)?;
+    }
+    let status = expand_word(&arithmetic.expression, shell)
+        .and_then(|expression| arith::evaluate(&expression, shell))
+        .map_or_else(|err| report_command_error(&io, &err), |value| (value == 0) as i32);
+
+    Ok((ExecResult::Continue, status))
+}
+
+/// Errors of a command itself go to its (possibly redirected) stderr. A
+/// builtin that writes into a closed pipe ends silently with the status of
+/// a process killed by `SIGPIPE`.
+fn report_command_error(io: &RedirectionInfo, err: &anyhow::Error) -> i32 {
+    if is_broken_pipe(err) {
+        return BROKEN_PIPE_STATUS;
+    }
+    let mut error_output = io.get_error_output();
+    let _ = error_output.println(&err.to_string());
+    error_output.close();
+    if err.is::<CommandNotFound>() {
+        127
+    } else {
+        1
+    }
+}
+
+fn run_args(
+    mut args: Vec<String>,
+    env: &[(String, String)],
+    io: &RedirectionInfo,
+    shell: &mut Shell,
+) -> Result<(ExecResult, i32)> {
+    let command = args.remove(0);
+    if let Some(function) = shell.function(&command) {
+        return run_function(&function, args, io, shell);
+    }
+
+    let mut output = io.get_output();
     let built_in_commands = get_builtin_commands();
 
-    let exec_result = match command {
-        "cd" => change_directory(&args),
+    let exec_result = match command.as_str() {
+        "cd" => change_directory(&args, shell),
         "echo" => {
             for arg in args {
-                print_out(&mut output, &mut out_str, piped, &format!("{arg} "));
+                output.print(&format!("{arg} "))?;
             }
-            println_out(&mut output, &mut out_str, piped, "");
+            output.println("")?;
             Ok(ExecResult::Continue)
         }
         "exit" => {
             let code = args
-                .get(0)
+                .first()
                 .unwrap_or(&"0".to_string())
                 .parse::<i32>()
                 .unwrap_or(1);
             Ok(ExecResult::Exit(code))
         }
-        "pwd" => print_current_dir(&mut output, &mut out_str, piped),
+        "pwd" => print_current_dir(shell, &mut output),
         "type" => {
-            let cmd = args.get(0).ok_or(anyhow!("Missing command argument"))?;
-            if built_in_commands.contains(cmd) {
-                println_out(
-                    &mut output,
-                    &mut out_str,
-                    piped,
-                    &format!("{cmd} is a shell builtin"),
-                );
+            let cmd = args.first().ok_or(anyhow!("Missing command argument"))?;
+            if let Some(text) = shell.aliases.get(cmd) {
+                output.println(&format!("{cmd} is aliased to `{text}'"))?;
+                Ok(ExecResult::Continue)
+            } else if RESERVED_WORDS.contains(&cmd.as_str()) {
+                output.println(&format!("{cmd} is a shell keyword"))?;
+                Ok(ExecResult::Continue)
+            } else if shell.function(cmd).is_some() {
+                output.println(&format!("{cmd} is a function"))?;
+                Ok(ExecResult::Continue)
+            } else if built_in_commands.contains(cmd) {
+                output.println(&format!("{cmd} is a shell builtin"))?;
                 Ok(ExecResult::Continue)
             } else {
-                find_command_in_path(cmd).map(|cmd_path| {
-                    println_out(
-                        &mut output,
-                        &mut out_str,
-                        piped,
-                        &format!("{cmd} is {cmd_path}"),
-                    );
-                    ExecResult::Continue
-                })
+                let cmd_path = find_command_in_path(cmd, None, shell)?;
+                output.println(&format!("{cmd} is {cmd_path}"))?;
+                Ok(ExecResult::Continue)
             }
         }
-        "history" => run_history(args, history, &mut output, &mut out_str, piped),
-        other => find_command_in_path(other).map(|_| {
-            let out_result =
-                run_process(other, &args, prev_output, is_part_of_pipe, is_last_in_pipe);
-            match out_result {
-                Ok(cmd_out) => {
-                    match &cmd_out {
-                        CommandOutput::Out(out) => {
-                            output.print(&format!("{}", String::from_utf8_lossy(&out.stdout)));
-                            error_output
-                                .print(&format!("{}", String::from_utf8_lossy(&out.stderr)));
-                        }
-                        _ => {}
-                    }
-                    command_output_opt = Some(cmd_out);
-                }
-                Err(err) => error_output.print(&format!("{}", err)),
+        "history" => run_history(args, &shell.cwd, &mut shell.history, &mut output),
+        "shopt" => {
+            let status = run_shopt(&args, shell, &mut output)?;
+            output.close();
+            return Ok((ExecResult::Continue, status));
+        }
+        "alias" => {
+            let status = run_alias(&args, io, shell, &mut output)?;
+            output.close();
+            return Ok((ExecResult::Continue, status));
+        }
+        "unalias" => {
+            let status = run_unalias(&args, io, shell)?;
+            output.close();
+            return Ok((ExecResult::Continue, status));
+        }
+        "read" => {
+            let status = run_read(&args, io, shell)?;
+            output.close();
+            return Ok((ExecResult::Continue, status));
+        }
+        "break" | "continue" => loop_control(&command, &args, io, shell),
+        "return" => {
+            let status = return_status(&args, shell)?;
+            output.close();
+            return Ok((ExecResult::Return(status), status));
+        }
+        "declare" | "typeset" | "export" | "readonly" | "local" => {
+            let status = run_declare(&command, &args, io, shell, &mut output)?;
+            output.close();
+            return Ok((ExecResult::Continue, status));
+        }
+        "unset" => {
+            let status = run_unset(&args, io, shell)?;
+            output.close();
+            return Ok((ExecResult::Continue, status));
+        }
+        "let" => {
+            let mut value = None;
+            for arg in &args {
+                value = Some(arith::evaluate(arg, shell)?);
             }
-            ExecResult::Continue
-        }),
+            let value = value.ok_or_else(|| anyhow!("let: expression expected"))?;
+            output.close();
+            return Ok((ExecResult::Continue, (value == 0) as i32));
+        }
+        other => {
+            let status = run_process(other, &args, env, io, shell)?;
+            return Ok((ExecResult::Continue, status));
+        }
     };
 
     output.close();
 
-    match exec_result {
-        Ok(exec_result) => {
-            let command_output = match command_output_opt {
-                Some(out) => out,
-                None => CommandOutput::Str(out_str),
-            };
-            Ok((exec_result, command_output))
-        }
-        Err(err) => Err(err),
+    exec_result.map(|exec_result| (exec_result, 0))
+}
+
+/// Runs a function with `args` as positional parameters. `return` ends it
+/// early.
+fn run_function(
+    function: &FunctionDefinition,
+    args: Vec<String>,
+    io: &RedirectionInfo,
+    shell: &mut Shell,
+) -> Result<(ExecResult, i32)> {
+    shell.push_frame(args);
+    let result = run_command(&function.body, io.clone(), shell);
+    shell.pop_frame();
+    match result? {
+        (ExecResult::Return(status), _) => Ok((ExecResult::Continue, status)),
+        result => Ok(result),
+    }
+}
+
+/// Returns the status for `return [n]`, which defaults to the one of the
+/// last command.
+fn return_status(args: &[String], shell: &Shell) -> Result<i32> {
+    if !shell.in_function() {
+        return Err(anyhow!("return: can only `return' from a function or sourced script"));
+    }
+    match args.first() {
+        Some(arg) => arg
+            .parse::<i32>()
+            .map_err(|_| anyhow!("return: {arg}: numeric argument required")),
+        None => Ok(shell.last_status),
     }
 }
 
 fn run_history(
     args: Vec<String>,
+    cwd: &Path,
     history: &mut History,
-    output: &mut Box<dyn Output>,
-    out_str: &mut String,
-    piped: bool) -> Result<ExecResult> {
+    output: &mut Box<dyn Output>) -> Result<ExecResult> {
 
     let entries = if args.is_empty() {
         history.get_all_entries()
@@ -171,15 +703,15 @@ fn run_history(
                 if args.len() < 2 {
                     return Err(anyhow!("syntax: history -r <path_to_history_file>"));
                 }
-                history.load(&args[1])?;
+                history.load(cwd.join(&args[1]))?;
                 return Ok(ExecResult::Continue);
             }
             "-w" => {
-                history.save(&args[1])?;
+                history.save(cwd.join(&args[1]))?;
                 return Ok(ExecResult::Continue);
             }
             "-a" => {
-                history.append(&args[1])?;
+                history.append(cwd.join(&args[1]))?;
                 return Ok(ExecResult::Continue);
             }
             _ => {
@@ -189,166 +721,492 @@ fn run_history(
         }
     };
     for (idx, input) in entries.iter().enumerate() {
-        println_out(
-            output,
-            out_str,
-            piped,
-            &format!("{:>5}  {}", idx + 1, input),
-        );
+        output.println(&format!("{:>5}  {}", idx + 1, input))?;
     }
     Ok(ExecResult::Continue)
 }
 
-fn run_process(
-    command: &str,
-    args: &Vec<String>,
-    prev_output: Option<CommandOutput>,
-    is_part_of_pipe: bool,
-    is_last_in_pipe: bool,
-) -> Result<CommandOutput> {
-    let mut cmd = Command::new(command);
-    cmd.args(args);
-
-    if !is_part_of_pipe {
-        let output = cmd.output();
-        return output
-            .map(|out| CommandOutput::Out(out))
-            .map_err(|err| err.into());
+/// `shopt [-s|-u] [-q] [name...]` enables, disables or shows options.
+fn run_shopt(args: &[String], shell: &mut Shell, output: &mut Box<dyn Output>) -> Result<i32> {
+    let mut enable = None;
+    let mut quiet = false;
+    let mut names = vec![];
+    for arg in args {
+        match arg.as_str() {
+            "-s" => enable = Some(true),
+            "-u" => enable = Some(false),
+            "-q" => quiet = true,
+            _ if arg.starts_with('-') => {
+                return Err(anyhow!("shopt: {arg}: invalid option\nshopt: usage: shopt [-squ] [optname ...]"))
+            }
+            _ => names.push(arg.as_str()),
+        }
     }
 
-    if !is_last_in_pipe {
-        cmd.stdout(Stdio::piped());
+    if let (Some(enable), false) = (enable, names.is_empty()) {
+        for name in names {
+            shell.set_option(name, enable)?;
+        }
+        return Ok(0);
     }
-    cmd.stderr(Stdio::piped());
 
-    if let Some(_) = prev_output {
-        cmd.stdin(Stdio::piped());
+    let options: Vec<(&str, bool)> = if names.is_empty() {
+        shell
+            .options()
+            .filter(|(_, enabled)| enable.map_or(true, |enable| enable == *enabled))
+            .collect()
+    } else {
+        let mut options = vec![];
+        for name in names {
+            if !shell.options().any(|(option, _)| option == name) {
+                return Err(anyhow!("shopt: {name}: invalid shell option name"));
+            }
+            options.push((name, shell.is_option_set(name)));
+        }
+        options
+    };
+    if !quiet {
+        for (name, enabled) in &options {
+            output.println(&format!("{name:<15}\t{}", if *enabled { "on" } else { "off" }))?;
+        }
     }
 
-    let mut child: Child;
+    Ok(if options.iter().all(|(_, enabled)| *enabled) { 0 } else { 1 })
+}
 
-    if let Some(output) = prev_output {
-        match output {
-            CommandOutput::Str(s) => {
-                child = cmd.spawn()?;
-                let mut stdin = child.stdin.take().unwrap();
-                stdin.write_all(s.as_bytes())?;
-            }
-            CommandOutput::Out(out) => {
-                child = cmd.spawn()?;
-                let mut stdin = child.stdin.take().unwrap();
-                stdin.write_all(&out.stdout)?;
+/// `alias [-p] [name[=value]...]` defines aliases or shows them in a form
+/// that can be reused as input.
+fn run_alias(
+    args: &[String],
+    io: &RedirectionInfo,
+    shell: &mut Shell,
+    output: &mut Box<dyn Output>,
+) -> Result<i32> {
+    let print_all = args.is_empty() || args.first().is_some_and(|arg| arg == "-p");
+    let args: Vec<&String> = args.iter().skip_while(|arg| *arg == "-p").collect();
+    if let Some(arg) = args.iter().find(|arg| arg.starts_with('-')) {
+        return Err(anyhow!("alias: {arg}: invalid option\nalias: usage: alias [-p] [name[=value] ... ]"));
+    }
+    let print = |output: &mut Box<dyn Output>, name: &str, text: &str| {
+        output.println(&format!("alias {name}='{}'", text.replace('\'', "'\\''")))
+    };
+    if print_all {
+        for (name, text) in &shell.aliases {
+            print(output, name, text)?;
+        }
+    }
+
+    let mut status = 0;
+    let mut error_output = io.get_error_output();
+    for arg in args {
+        match arg.split_once('=') {
+            Some((name, _)) if name.is_empty() || name.contains(is_alias_delimiter) => {
+                error_output.println(&format!("alias: `{name}': invalid alias name"))?;
+                status = 1;
             }
-            CommandOutput::ChildOut(stdout) => {
-                cmd.stdin(stdout);
-                child = cmd.spawn()?;
+            Some((name, text)) => {
+                shell.aliases.insert(name.to_string(), text.to_string());
             }
+            None => match shell.aliases.get(arg.as_str()) {
+                Some(text) => print(output, arg, text)?,
+                None => {
+                    error_output.println(&format!("alias: {arg}: not found"))?;
+                    status = 1;
+                }
+            },
         }
-    } else {
-        child = cmd.spawn()?;
     }
+    error_output.close();
+    Ok(status)
+}
 
-    let output = if is_last_in_pipe {
-        CommandOutput::Out(child.wait_with_output()?)
-    } else {
-        CommandOutput::ChildOut(child.stdout.unwrap())
-    };
-
-    Ok(output)
+/// Characters that cannot be part of an alias name.
+fn is_alias_delimiter(ch: char) -> bool {
+    ch.is_whitespace() || "/$`\\'\"=|&;()<>".contains(ch)
 }
 
-fn print_out(out: &mut Box<dyn Output>, out_str: &mut String, piped: bool, text: &str) {
-    if !piped {
-        out.print(text);
+/// `unalias [-a] name...` removes the given or with `-a` all aliases.
+fn run_unalias(args: &[String], io: &RedirectionInfo, shell: &mut Shell) -> Result<i32> {
+    if args.first().is_some_and(|arg| arg == "-a") {
+        shell.aliases.clear();
+        return Ok(0);
+    }
+    if args.is_empty() {
+        return Err(anyhow!("unalias: usage: unalias [-a] name [name ...]"));
+    }
+
+    let mut status = 0;
+    let mut error_output = io.get_error_output();
+    for name in args {
+        if shell.aliases.remove(name).is_none() {
+            error_output.println(&format!("unalias: {name}: not found"))?;
+            status = 1;
+        }
     }
-    out_str.push_str(text);
+    error_output.close();
+    Ok(status)
 }
 
-fn println_out(out: &mut Box<dyn Output>, out_str: &mut String, piped: bool, text: &str) {
-    if !piped {
-        out.println(text);
+/// `declare [-p] [-+ilrux] [name[=value]...]` and its variants `typeset`,
+/// `local`, `export [-n]` and `readonly` set attributes and values of
+/// variables. Without names they list the variables that have the given
+/// attributes. In functions `declare` and `local` create local variables.
+fn run_declare(
+    command: &str,
+    args: &[String],
+    io: &RedirectionInfo,
+    shell: &mut Shell,
+    output: &mut Box<dyn Output>,
+) -> Result<i32> {
+    let local = match command {
+        "local" if !shell.in_function() => return Err(anyhow!("local: can only be used in a function")),
+        "local" => true,
+        "declare" | "typeset" => shell.in_function(),
+        _ => false,
+    };
+    let mut attributes = match command {
+        "export" => vec![('x', true)],
+        "readonly" => vec![('r', true)],
+        _ => vec![],
+    };
+    let mut print = false;
+    let mut idx = 0;
+    while let Some(arg) = args.get(idx) {
+        let enabled = match arg.chars().next() {
+            _ if arg == "--" => {
+                idx += 1;
+                break;
+            }
+            Some('-') if arg.len() > 1 => true,
+            Some('+') if arg.len() > 1 => false,
+            _ => break,
+        };
+        for flag in arg[1..].chars() {
+            match flag {
+                'p' => print = true,
+                'n' if command == "export" => attributes = vec![('x', !enabled)],
+                _ if Variable::FLAGS.contains(&flag) && !matches!(command, "export" | "readonly") => {
+                    attributes.push((flag, enabled));
+                }
+                _ => return Err(anyhow!("{command}: {}{flag}: invalid option", &arg[..1])),
+            }
+        }
+        idx += 1;
     }
-    out_str.push_str(text);
-    if !piped {
-        out_str.push('\n');
+    let names = &args[idx..];
+
+    if names.is_empty() {
+        let variables = shell.variables().filter(|(_, variable)| {
+            attributes
+                .iter()
+                .all(|(flag, enabled)| !enabled || variable.has_flag(*flag))
+        });
+        for (name, variable) in variables {
+            output.println(&declaration(name, variable))?;
+        }
+        return Ok(0);
     }
-}
 
-fn check_for_redirections(args: &Vec<String>) -> (Vec<String>, RedirectionInfo) {
-    let mut redirection_info = RedirectionInfo::new();
-    let mut new_args = Vec::new();
-    let num_args = args.len();
-    if num_args == 0 {
-        return (args.clone(), redirection_info);
+    let mut status = 0;
+    let mut error_output = io.get_error_output();
+    for arg in names {
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (arg.as_str(), None),
+        };
+        let result = if print {
+            match shell.variable(arg) {
+                Some(variable) => {
+                    output.println(&declaration(arg, variable))?;
+                    Ok(())
+                }
+                None => Err(anyhow!("{arg}: not found")),
+            }
+        } else if !is_valid_name(name) {
+            Err(anyhow!("`{arg}': not a valid identifier"))
+        } else {
+            declare(shell, name, value, &attributes, local)
+        };
+        if let Err(err) = result {
+            error_output.println(&format!("{command}: {err}"))?;
+            status = 1;
+        }
     }
+    error_output.close();
+    Ok(status)
+}
 
-    let mut i = 0;
+/// Applies `attributes` to a variable and assigns `value` to it. `-r` is
+/// set last, so that `readonly name=value` can still assign the value.
+fn declare(
+    shell: &mut Shell,
+    name: &str,
+    value: Option<&str>,
+    attributes: &[(char, bool)],
+    local: bool,
+) -> Result<()> {
+    if local {
+        shell.make_local(name)?;
+    }
+    for &(flag, enabled) in attributes.iter().filter(|(flag, _)| *flag != 'r') {
+        shell.set_attribute(name, flag, enabled)?;
+    }
+    match value {
+        Some(value) => shell.set_var(name, value)?,
+        None => shell.declare_var(name),
+    }
+    for &(flag, enabled) in attributes.iter().filter(|(flag, _)| *flag == 'r') {
+        shell.set_attribute(name, flag, enabled)?;
+    }
+    Ok(())
+}
 
-    while i < num_args {
-        let arg = &args[i];
-        if i == num_args - 1 {
-            new_args.push(arg.to_string());
-            i += 1;
-            continue;
+/// Formats a variable like `declare -p` does, e.g. `declare -x HOME="/root"`.
+fn declaration(name: &str, variable: &Variable) -> String {
+    let flags = match variable.flags() {
+        flags if flags.is_empty() => "-".to_string(),
+        flags => flags,
+    };
+    match &variable.value {
+        Some(value) => {
+            let mut quoted = String::new();
+            for ch in value.chars() {
+                if matches!(ch, '"' | '\\' | '$' | '`') {
+                    quoted.push('\\');
+                }
+                quoted.push(ch);
+            }
+            format!("declare -{flags} {name}=\"{quoted}\"")
         }
+        None => format!("declare -{flags} {name}"),
+    }
+}
+
+/// `unset [-f] [-v] name...` removes variables or, with `-f` or if there is
+/// no such variable, functions.
+fn run_unset(args: &[String], io: &RedirectionInfo, shell: &mut Shell) -> Result<i32> {
+    let mut functions = None;
+    let mut idx = 0;
+    while let Some(arg) = args.get(idx).filter(|arg| arg.starts_with('-') && arg.len() > 1) {
+        idx += 1;
         match arg.as_str() {
-            ">" | "1>" => {
-                let file_path = args[i + 1].clone();
-                redirection_info.redirect_stdout(file_path, FileOpenMode::Create);
-                i += 2;
+            "--" => break,
+            "-f" => functions = Some(true),
+            "-v" => functions = Some(false),
+            _ => return Err(anyhow!("unset: {arg}: invalid option\nunset: usage: unset [-f] [-v] [name ...]")),
+        }
+    }
+
+    let mut status = 0;
+    let mut error_output = io.get_error_output();
+    for name in &args[idx..] {
+        let result = match functions {
+            Some(true) => {
+                shell.unset_function(name);
+                Ok(())
             }
-            "2>" => {
-                let file_path = args[i + 1].clone();
-                redirection_info.redirect_stderr(file_path, FileOpenMode::Create);
-                i += 2;
+            _ if !is_valid_name(name) => Err(anyhow!("`{name}': not a valid identifier")),
+            None if shell.variable(name).is_none() => {
+                shell.unset_function(name);
+                Ok(())
             }
-            ">>" | "1>>" => {
-                let file_path = args[i + 1].clone();
-                redirection_info.redirect_stdout(file_path, FileOpenMode::Append);
-                i += 2;
+            _ => shell.unset_var(name),
+        };
+        if let Err(err) = result {
+            error_output.println(&format!("unset: {err}"))?;
+            status = 1;
+        }
+    }
+    error_output.close();
+    Ok(status)
+}
+
+/// `read [-r] [name...]` reads a line from stdin and assigns its fields to
+/// the names. The last name gets the rest of the line.
+fn run_read(args: &[String], io: &RedirectionInfo, shell: &mut Shell) -> Result<i32> {
+    let mut raw = false;
+    let mut names = vec![];
+    for arg in args {
+        match arg.as_str() {
+            "-r" => raw = true,
+            _ if arg.starts_with('-') => {
+                return Err(anyhow!("read: {arg}: invalid option\nread: usage: read [-r] [name ...]"))
             }
-            "2>>" => {
-                let file_path = args[i + 1].clone();
-                redirection_info.redirect_stderr(file_path, FileOpenMode::Append);
-                i += 2;
+            _ if !is_valid_name(arg) => return Err(anyhow!("read: `{arg}': not a valid identifier")),
+            _ => names.push(arg.as_str()),
+        }
+    }
+    if names.is_empty() {
+        names.push("REPLY");
+    }
+
+    let mut input = io
+        .get_input()
+        .ok_or_else(|| anyhow!("read: read error: 0: Bad file descriptor"))?;
+    let mut line = vec![];
+    let mut byte = [0u8];
+    let mut at_eof = true;
+    // Read byte by byte, so that nothing after the line is consumed
+    while input.read(&mut byte)? == 1 {
+        match byte[0] {
+            b'\n' => {
+                at_eof = false;
+                break;
             }
-            _ => {
-                new_args.push(arg.clone());
-                i += 1;
+            b'\\' if !raw => {
+                if input.read(&mut byte)? == 1 && byte[0] != b'\n' {
+                    line.push(byte[0]);
+                }
             }
+            other => line.push(other),
         }
     }
+    if at_eof && line.is_empty() {
+        return Ok(1);
+    }
 
-    (new_args, redirection_info)
+    let line = String::from_utf8_lossy(&line);
+    let ifs = shell.get_parameter("IFS");
+    let fields = split_fields(&line, ifs.as_deref(), Some(names.len()));
+    for (idx, name) in names.iter().enumerate() {
+        shell.set_var(name, fields.get(idx).map_or("", String::as_str))?;
+    }
+    Ok(at_eof as i32)
 }
 
-fn change_directory(args: &Vec<String>) -> Result<ExecResult> {
-    let dir = match args.len() {
-        0 => &get_home_dir()?,
-        1 => {
-            if args[0] != "~".to_string() {
-                &args[0]
+/// `break [n]` and `continue [n]` leave the `n` innermost loops or continue
+/// with the next iteration of the `n`-th one. Outside of loops they only
+/// print a warning.
+fn loop_control(command: &str, args: &[String], io: &RedirectionInfo, shell: &Shell) -> Result<ExecResult> {
+    let count = match args.first() {
+        None => 1,
+        Some(arg) => match arg.parse::<i64>() {
+            Ok(count) if count > 0 => count as usize,
+            Ok(_) => return Err(anyhow!("{command}: {arg}: loop count out of range")),
+            Err(_) => return Err(anyhow!("{command}: {arg}: numeric argument required")),
+        },
+    };
+    if shell.loop_depth == 0 {
+        let mut error_output = io.get_error_output();
+        error_output.println(&format!("{command}: only meaningful in a `for', `while', or `until' loop"))?;
+        error_output.close();
+        return Ok(ExecResult::Continue);
+    }
+
+    let count = count.min(shell.loop_depth);
+    Ok(match command {
+        "break" => ExecResult::Break(count),
+        _ => ExecResult::NextIteration(count),
+    })
+}
+
+/// Runs an external command with the exported variables and the
+/// assignments in front of it as environment. A command without `/` is
+/// looked up in the `PATH` of that environment.
+fn run_process(
+    command: &str,
+    args: &[String],
+    env: &[(String, String)],
+    io: &RedirectionInfo,
+    shell: &Shell,
+) -> Result<i32> {
+    let program = if command.contains('/') {
+        shell.resolve_path(command).display().to_string()
+    } else {
+        let path_var = env.iter().rev().find(|(name, _)| name == "PATH").map(|(_, value)| value.as_str());
+        find_command_in_path(command, path_var, shell)?
+    };
+    let mut cmd = Command::new(program);
+    cmd.arg0(command).args(args).current_dir(&shell.cwd).env_clear().envs(shell.exported_vars()).envs(env.iter().cloned());
+    io.apply_to(&mut cmd)?;
+
+    let status = cmd.status()?;
+    Ok(exit_code(status))
+}
+
+fn exit_code(status: ExitStatus) -> i32 {
+    match status.code() {
+        Some(code) => code,
+        None => 128 + status.signal().unwrap_or(0),
+    }
+}
+
+fn apply_redirect(io: &mut RedirectionInfo, redirect: &Redirect, shell: &mut Shell) -> Result<()> {
+    let target = match redirect.op {
+        RedirectOp::HereDoc { .. } => expand_word(&redirect.target, shell)?,
+        _ => expand_word(&tilde::expand_tilde(&redirect.target, shell), shell)?,
+    };
+    let open_mode = match redirect.op {
+        RedirectOp::Output => FileOpenMode::Create,
+        RedirectOp::Append => FileOpenMode::Append,
+        RedirectOp::Input => FileOpenMode::Read,
+        RedirectOp::ReadWrite => FileOpenMode::ReadWrite,
+        RedirectOp::DupOutput | RedirectOp::DupInput => {
+            return duplicate_fd(io, redirect.fd, redirect.op, &target, &shell.cwd);
+        }
+        RedirectOp::OutputAll | RedirectOp::AppendAll => {
+            let open_mode = if redirect.op == RedirectOp::OutputAll {
+                FileOpenMode::Create
             } else {
-                &get_home_dir()?
-            }
+                FileOpenMode::Append
+            };
+            io.redirect(1, &target, &shell.cwd, open_mode)?;
+            return io.duplicate(2, 1);
         }
-        _ => return Err(anyhow!("cd allows no more then one argument")),
+        RedirectOp::HereDoc { .. } => return io.feed(redirect.fd, target),
+        RedirectOp::HereString => return io.feed(redirect.fd, format!("{target}\n")),
     };
+    io.redirect(redirect.fd, &target, &shell.cwd, open_mode)
+}
 
-    match env::set_current_dir(dir) {
-        Ok(_) => Ok(ExecResult::Continue),
-        Err(_) => Err(anyhow!("cd: {}: No such file or directory", &args[0])),
+fn duplicate_fd(io: &mut RedirectionInfo, fd: i32, op: RedirectOp, source: &str, cwd: &Path) -> Result<()> {
+    if source == "-" {
+        io.close(fd);
+        return Ok(());
     }
+    match source.parse::<i32>() {
+        Ok(source_fd) => io.duplicate(fd, source_fd),
+        // `>&file` is the same as `&>file`
+        Err(_) if op == RedirectOp::DupOutput && fd == 1 => {
+            io.redirect(1, source, cwd, FileOpenMode::Create)?;
+            io.duplicate(2, 1)
+        }
+        Err(_) => Err(anyhow!("{}: ambiguous redirect", source)),
+    }
+}
+
+fn change_directory(args: &[String], shell: &mut Shell) -> Result<ExecResult> {
+    let dir = match args.len() {
+        0 => &get_home_dir(shell)?,
+        1 => &args[0],
+        _ => return Err(anyhow!("cd allows no more then one argument")),
+    };
+
+    let new_dir = match fs::canonicalize(shell.resolve_path(dir)) {
+        Ok(new_dir) if new_dir.is_dir() => new_dir,
+        _ => return Err(anyhow!("cd: {dir}: No such file or directory")),
+    };
+    let old_dir = std::mem::replace(&mut shell.cwd, new_dir);
+    shell.set_var("OLDPWD", &old_dir.display().to_string())?;
+    shell.set_var("PWD", &shell.cwd.display().to_string())?;
+    Ok(ExecResult::Continue)
 }
 
-fn get_home_dir() -> Result<String> {
-    env::var("HOME").map_err(|_| anyhow!("$HOME is not set"))
+fn get_home_dir(shell: &Shell) -> Result<String> {
+    shell
+        .get_parameter("HOME")
+        .ok_or_else(|| anyhow!("cd: HOME not set"))
 }
 
-fn find_command_in_path(command: &str) -> Result<String> {
-    let path_var = env::var("PATH")?;
+/// Returns the path of `command` in the directories of `path_var`, or of
+/// `PATH` if it is `None`.
+fn find_command_in_path(command: &str, path_var: Option<&str>, shell: &Shell) -> Result<String> {
+    let path_var = path_var
+        .map(str::to_string)
+        .or_else(|| shell.get_parameter("PATH"))
+        .ok_or_else(|| CommandNotFound(command.to_string()))?;
     for path in env::split_paths(&path_var) {
-        let mut exec_path = path;
+        let mut exec_path = shell.resolve_path(path);
         exec_path.push(command);
         match exec_path.try_exists() {
             Ok(true) => match exec_path.to_str() {
@@ -359,20 +1217,27 @@ fn find_command_in_path(command: &str) -> Result<String> {
         }
     }
 
-    Err(anyhow!("{command}: not found"))
+    Err(CommandNotFound(command.to_string()).into())
 }
 
-fn print_current_dir(
-    output: &mut Box<dyn Output>,
-    out_str: &mut String,
-    piped: bool,
-) -> Result<ExecResult> {
-    let current_dir = env::current_dir()?;
-    println_out(
-        output,
-        out_str,
-        piped,
-        &format!("{}", current_dir.display()),
-    );
+fn print_current_dir(shell: &Shell, output: &mut Box<dyn Output>) -> Result<ExecResult> {
+    output.println(&format!("{}", shell.cwd.display()))?;
     Ok(ExecResult::Continue)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_declaration() {
+        let variable = Variable {
+            value: Some("a\"b$c\\d`e".to_string()),
+            readonly: true,
+            exported: true,
+            ..Variable::default()
+        };
+        assert_eq!(declaration("q", &variable), r#"declare -rx q="a\"b\$c\\d\`e""#);
+        assert_eq!(declaration("u", &Variable::default()), "declare -- u");
+    }
+}
@@ -0,0 +1,678 @@
+use crate::arith;
+use crate::ast::{CommandList, ParameterExpansion, ParameterOp, Word, WordPart};
+use crate::brace;
+use crate::cmd;
+use crate::glob::{self, GlobOptions};
+use crate::pattern::{self, Pattern};
+use crate::redirect::{pipe, RedirectionInfo};
+use crate::shell::{is_valid_name, Shell};
+use crate::tilde;
+use anyhow::{anyhow, Result};
+use std::io::Read;
+use std::ops::Range;
+use std::thread;
+
+/// Result of expanding a word. Every character remembers where it comes
+/// from: quoted characters are not special in patterns and only the results
+/// of unquoted expansions are split into fields.
+#[derive(Debug, Default)]
+struct Field {
+    chars: Vec<(char, Source)>,
+    /// Whether the word contained quotes. Quoted empty words are kept as an
+    /// empty field.
+    quoted: bool,
+    /// Positions of the spaces between the parameters of `"$@"`. They
+    /// always separate fields.
+    breaks: Vec<usize>,
+}
+
+/// Origin of a character in a field.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Source {
+    Literal,
+    Quoted,
+    Expansion,
+}
+
+/// Separators used for field splitting if `IFS` is unset.
+const DEFAULT_IFS: &str = " \t\n";
+
+impl Field {
+    fn push_str(&mut self, text: &str, source: Source) {
+        self.chars.extend(text.chars().map(|ch| (ch, source)));
+        self.quoted |= source == Source::Quoted;
+    }
+
+    fn text(&self) -> String {
+        self.chars.iter().map(|(ch, _)| ch).collect()
+    }
+
+    /// Returns the field as pattern in which quoted characters only match
+    /// themselves.
+    fn pattern(&self) -> String {
+        let mut pattern = String::new();
+        for (ch, source) in &self.chars {
+            match source {
+                Source::Quoted => pattern.push_str(&pattern::escape(&ch.to_string())),
+                _ => pattern.push(*ch),
+            }
+        }
+        pattern
+    }
+
+    fn has_wildcards(&self, extglob: bool) -> bool {
+        pattern::has_wildcards(&self.pattern(), extglob)
+    }
+
+    /// Splits the field at its breaks and then each part at the characters
+    /// in `ifs` that result from unquoted expansions.
+    fn split(self, ifs: &str) -> Vec<Field> {
+        let mut fields = vec![];
+        let mut start = 0;
+        for end in self.breaks.iter().copied().chain([self.chars.len()]) {
+            let part = Field {
+                chars: self.chars[start..end].to_vec(),
+                quoted: self.quoted,
+                breaks: vec![],
+            };
+            fields.extend(part.split_ifs(ifs));
+            start = end + 1;
+        }
+        fields
+    }
+
+    fn split_ifs(self, ifs: &str) -> Vec<Field> {
+        let ranges = split_ranges(self.chars.len(), None, |idx| match self.chars[idx] {
+            (ch, Source::Expansion) => ifs_separator(ifs, ch),
+            _ => None,
+        });
+        let mut fields: Vec<Field> = ranges
+            .into_iter()
+            .map(|range| Field {
+                chars: self.chars[range].to_vec(),
+                quoted: self.quoted,
+                breaks: vec![],
+            })
+            .collect();
+        if fields.is_empty() && self.quoted {
+            fields.push(Field {
+                chars: vec![],
+                quoted: true,
+                breaks: vec![],
+            });
+        }
+        fields
+    }
+}
+
+/// Splits `text` into fields like the results of unquoted expansions are
+/// split. `ifs` is the value of `IFS` or `None` if it is unset. With a
+/// `limit`, the last field holds the rest of the text like with `read`.
+pub fn split_fields(text: &str, ifs: Option<&str>, limit: Option<usize>) -> Vec<String> {
+    let ifs = ifs.unwrap_or(DEFAULT_IFS);
+    let chars: Vec<char> = text.chars().collect();
+    split_ranges(chars.len(), limit, |idx| ifs_separator(ifs, chars[idx]))
+        .into_iter()
+        .map(|range| chars[range].iter().collect())
+        .collect()
+}
+
+/// Returns `Some(true)` for IFS whitespace, `Some(false)` for other IFS
+/// characters and `None` for characters that do not separate fields.
+fn ifs_separator(ifs: &str, ch: char) -> Option<bool> {
+    ifs.contains(ch).then(|| DEFAULT_IFS.contains(ch))
+}
+
+/// Returns the ranges of the fields in a sequence of `len` elements.
+/// Sequences of IFS whitespace separate fields and are ignored at the start
+/// and end. Every other separator ends a field, even an empty one, and
+/// absorbs the IFS whitespace around it. With a `limit`, the last field
+/// extends to the end, only trailing IFS whitespace is removed.
+fn split_ranges(
+    len: usize,
+    limit: Option<usize>,
+    separator: impl Fn(usize) -> Option<bool>,
+) -> Vec<Range<usize>> {
+    let skip_whitespace = |mut idx: usize| {
+        while idx < len && separator(idx) == Some(true) {
+            idx += 1;
+        }
+        idx
+    };
+
+    let mut ranges = vec![];
+    let mut idx = skip_whitespace(0);
+    let mut start = idx;
+    while idx < len && limit.map_or(true, |limit| ranges.len() + 1 < limit) {
+        let Some(is_whitespace) = separator(idx) else {
+            idx += 1;
+            continue;
+        };
+        ranges.push(start..idx);
+        idx = skip_whitespace(idx + 1);
+        if is_whitespace && idx < len && separator(idx) == Some(false) {
+            idx = skip_whitespace(idx + 1);
+        }
+        start = idx;
+    }
+    let mut end = len;
+    while end > start && separator(end - 1) == Some(true) {
+        end -= 1;
+    }
+    if start < end {
+        ranges.push(start..end);
+    }
+    ranges
+}
+
+/// Builtins whose `name=value` arguments are expanded like assignments.
+const DECLARATION_COMMANDS: [&str; 5] = ["declare", "export", "local", "readonly", "typeset"];
+
+/// Expands the words of a command into its arguments. The results of
+/// unquoted expansions are split into fields, so that unquoted words that
+/// expand to nothing are removed. Fields with wildcards are replaced by the
+/// matching paths.
+pub fn expand_words(words: &[Word], shell: &mut Shell) -> Result<Vec<String>> {
+    let is_declaration = words
+        .first()
+        .is_some_and(|word| DECLARATION_COMMANDS.contains(&word.unquoted().as_str()));
+    let mut args = vec![];
+    for word in words.iter().flat_map(brace::expand_braces) {
+        let word = tilde::expand_tilde(&word, shell);
+        if is_declaration && word.assignment_name().is_some() {
+            args.push(expand_word(&word, shell)?);
+            continue;
+        }
+        let ifs = shell.get_parameter("IFS");
+        for field in expand_parts(&word.parts, shell)?.split(ifs.as_deref().unwrap_or(DEFAULT_IFS))
+        {
+            if !field.has_wildcards(shell.is_option_set("extglob")) {
+                args.push(field.text());
+                continue;
+            }
+
+            let paths = glob::glob(&field.pattern(), &shell.cwd, &glob_options(shell));
+            if !paths.is_empty() {
+                args.extend(paths);
+            } else if shell.is_option_set("failglob") {
+                return Err(anyhow!("no match: {}", field.text()));
+            } else if !shell.is_option_set("nullglob") {
+                args.push(field.text());
+            }
+        }
+    }
+    Ok(args)
+}
+
+fn glob_options(shell: &Shell) -> GlobOptions {
+    GlobOptions {
+        dotglob: shell.is_option_set("dotglob"),
+        globstar: shell.is_option_set("globstar"),
+        nocaseglob: shell.is_option_set("nocaseglob"),
+        extglob: shell.is_option_set("extglob"),
+    }
+}
+
+/// Expands an assignment `name=value` into the name and the value. Like in
+/// the arguments of declaration commands, the value is not split into
+/// fields.
+pub fn expand_assignment(word: &Word, shell: &mut Shell) -> Result<(String, String)> {
+    let text = expand_word(&tilde::expand_tilde(word, shell), shell)?;
+    let (name, value) = text.split_once('=').unwrap_or((&text, ""));
+    Ok((name.to_string(), value.to_string()))
+}
+
+/// Expands a single word, e.g. the target of a redirection, to a string.
+pub fn expand_word(word: &Word, shell: &mut Shell) -> Result<String> {
+    Ok(expand_parts(&word.parts, shell)?.text())
+}
+
+fn expand_parts(parts: &[WordPart], shell: &mut Shell) -> Result<Field> {
+    let mut field = Field::default();
+    for part in parts {
+        match part {
+            WordPart::Literal(s) => field.push_str(s, Source::Literal),
+            WordPart::SingleQuoted(s) => field.push_str(s, Source::Quoted),
+            WordPart::Escaped(ch) => field.push_str(&ch.to_string(), Source::Quoted),
+            WordPart::DoubleQuoted(parts) => expand_double_quoted(parts, &mut field, shell)?,
+            WordPart::Parameter(parameter) => {
+                field.push_str(&expand_parameter(parameter, shell)?, Source::Expansion)
+            }
+            WordPart::CommandSubstitution(commands) => {
+                field.push_str(&substitute_command(commands, shell)?, Source::Expansion)
+            }
+            WordPart::Arithmetic(expression) => field.push_str(
+                &expand_arithmetic(expression, shell)?.to_string(),
+                Source::Expansion,
+            ),
+        }
+    }
+    Ok(field)
+}
+
+/// Expands the parts between double quotes. Each positional parameter of
+/// `"$@"` becomes a separate field, so that there is none if there are no
+/// positional parameters.
+fn expand_double_quoted(parts: &[WordPart], field: &mut Field, shell: &mut Shell) -> Result<()> {
+    if parts.is_empty() {
+        field.push_str("", Source::Quoted);
+    }
+    for part in parts {
+        match part {
+            WordPart::Parameter(ParameterExpansion {
+                name,
+                op: ParameterOp::Value,
+            }) if name == "@" => {
+                for (idx, arg) in shell.positional.iter().enumerate() {
+                    if idx > 0 {
+                        field.breaks.push(field.chars.len());
+                        field.push_str(" ", Source::Quoted);
+                    }
+                    field.push_str(arg, Source::Quoted);
+                }
+            }
+            part => {
+                let text = expand_parts(std::slice::from_ref(part), shell)?.text();
+                field.push_str(&text, Source::Quoted);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Expands a word that is used as pattern. Quoted characters are escaped,
+/// so that they only match themselves.
+pub fn expand_pattern(word: &Word, shell: &mut Shell) -> Result<Pattern> {
+    let pattern = expand_parts(&word.parts, shell)?.pattern();
+    Ok(Pattern::with_extglob(&pattern, shell.is_option_set("extglob")))
+}
+
+/// Runs the commands like a subshell and returns their output without
+/// trailing newlines.
+fn substitute_command(commands: &CommandList, shell: &mut Shell) -> Result<String> {
+    let (mut reader, writer) = pipe()?;
+    let mut io = RedirectionInfo::new();
+    io.set(1, writer);
+
+    // Read concurrently, so that the commands cannot block on a full pipe
+    let output = thread::spawn(move || {
+        let mut output = vec![];
+        let _ = reader.read_to_end(&mut output);
+        output
+    });
+    shell.last_status = cmd::run_subshell(commands, &io, shell);
+    drop(io);
+
+    let output = output.join().unwrap_or_default();
+    let output = String::from_utf8_lossy(&output);
+    Ok(output.trim_end_matches('\n').to_string())
+}
+
+fn expand_parameter(parameter: &ParameterExpansion, shell: &mut Shell) -> Result<String> {
+    let name = &parameter.name;
+    let value = shell.get_parameter(name);
+    let is_set = |check_null: bool| match &value {
+        Some(value) => !check_null || !value.is_empty(),
+        None => false,
+    };
+
+    match &parameter.op {
+        ParameterOp::Value => Ok(value.unwrap_or_default()),
+        ParameterOp::Default { word, check_null } => match is_set(*check_null) {
+            true => Ok(value.unwrap_or_default()),
+            false => expand_word(word, shell),
+        },
+        ParameterOp::Assign { word, check_null } => {
+            if is_set(*check_null) {
+                return Ok(value.unwrap_or_default());
+            }
+            if !is_valid_name(name) {
+                return Err(anyhow!("${name}: cannot assign in this way"));
+            }
+            let value = expand_word(word, shell)?;
+            shell.set_var(name, &value)?;
+            Ok(shell.get_parameter(name).unwrap_or_default())
+        }
+        ParameterOp::Error { word, check_null } => {
+            if is_set(*check_null) {
+                return Ok(value.unwrap_or_default());
+            }
+            let message = expand_word(word, shell)?;
+            if message.is_empty() {
+                Err(anyhow!("{name}: parameter null or not set"))
+            } else {
+                Err(anyhow!("{name}: {message}"))
+            }
+        }
+        ParameterOp::Alternative { word, check_null } => match is_set(*check_null) {
+            true => expand_word(word, shell),
+            false => Ok(String::new()),
+        },
+        ParameterOp::Length => Ok(value.unwrap_or_default().chars().count().to_string()),
+        ParameterOp::RemovePrefix { pattern, longest } => {
+            let chars: Vec<char> = value.unwrap_or_default().chars().collect();
+            let len = expand_pattern(pattern, shell)?
+                .match_prefix(&chars, *longest)
+                .unwrap_or(0);
+            Ok(chars[len..].iter().collect())
+        }
+        ParameterOp::RemoveSuffix { pattern, longest } => {
+            let chars: Vec<char> = value.unwrap_or_default().chars().collect();
+            let len = expand_pattern(pattern, shell)?
+                .match_suffix(&chars, *longest)
+                .unwrap_or(0);
+            Ok(chars[..chars.len() - len].iter().collect())
+        }
+        ParameterOp::Replace {
+            pattern,
+            replacement,
+            all,
+        } => {
+            let value = value.unwrap_or_default();
+            let pattern = expand_pattern(pattern, shell)?;
+            let replacement = expand_word(replacement, shell)?;
+            Ok(replace_matches(&value, &pattern, &replacement, *all))
+        }
+        ParameterOp::Substring { offset, length } => {
+            let chars: Vec<char> = value.unwrap_or_default().chars().collect();
+            let offset = expand_arithmetic(offset, shell)?;
+            let length = match length {
+                Some(length) => Some(expand_arithmetic(length, shell)?),
+                None => None,
+            };
+            substring(&chars, offset, length)
+        }
+        ParameterOp::CaseModification {
+            upper,
+            all,
+            pattern,
+        } => {
+            let pattern = match pattern.parts.is_empty() {
+                true => Pattern::new("?"),
+                false => expand_pattern(pattern, shell)?,
+            };
+            let mut text = String::new();
+            for (idx, ch) in value.unwrap_or_default().chars().enumerate() {
+                if (idx == 0 || *all) && pattern.matches(&ch.to_string()) {
+                    match upper {
+                        true => text.extend(ch.to_uppercase()),
+                        false => text.extend(ch.to_lowercase()),
+                    }
+                } else {
+                    text.push(ch);
+                }
+            }
+            Ok(text)
+        }
+    }
+}
+
+/// Replaces the first or all longest non-empty matches of `pattern`.
+fn replace_matches(value: &str, pattern: &Pattern, replacement: &str, all: bool) -> String {
+    let chars: Vec<char> = value.chars().collect();
+    let mut text = String::new();
+    let mut pos = 0;
+    let mut replaced = false;
+
+    while pos < chars.len() {
+        if !replaced || all {
+            if let Some(len) = pattern
+                .match_prefix(&chars[pos..], true)
+                .filter(|len| *len > 0)
+            {
+                text.push_str(replacement);
+                pos += len;
+                replaced = true;
+                continue;
+            }
+        }
+        text.push(chars[pos]);
+        pos += 1;
+    }
+    text
+}
+
+/// Expands a word and evaluates the result as arithmetic expression.
+fn expand_arithmetic(word: &Word, shell: &mut Shell) -> Result<i64> {
+    let expression = expand_word(word, shell)?;
+    arith::evaluate(&expression, shell)
+}
+
+/// Returns `length` characters starting at `offset`. Negative numbers count
+/// from the end of the value.
+fn substring(chars: &[char], offset: i64, length: Option<i64>) -> Result<String> {
+    let len = chars.len() as i64;
+    let start = if offset < 0 { len + offset } else { offset };
+    if start < 0 || start > len {
+        return Ok(String::new());
+    }
+    let end = match length {
+        Some(length) if length < 0 => len + length,
+        Some(length) => (start + length).min(len),
+        None => len,
+    };
+    if end < start {
+        return Err(anyhow!("{}: substring expression < 0", end - start));
+    }
+    Ok(chars[start as usize..end as usize].iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arg_parse::ArgParser;
+    use crate::ast::Command;
+
+    fn expand(input: &str, shell: &mut Shell) -> Result<Vec<String>> {
+        let commands = ArgParser::new().parse_args(input)?;
+        let Command::Simple(simple) = &commands.items[0].1.commands[0] else {
+            panic!("simple command expected");
+        };
+        expand_words(&simple.words, shell)
+    }
+
+    #[test]
+    fn test_expand_variables() {
+        let mut shell = Shell::new();
+        shell.set_var("name", "world").unwrap();
+        shell.set_var("empty", "").unwrap();
+
+        let args = expand(
+            "echo $name \"hello ${name}!\" '$name' \\$name $empty \"$empty\"",
+            &mut shell,
+        );
+        assert_eq!(
+            args.unwrap(),
+            vec!["echo", "world", "hello world!", "$name", "$name", ""]
+        );
+    }
+
+    #[test]
+    fn test_expand_default_and_alternative() {
+        let mut shell = Shell::new();
+        shell.set_var("empty", "").unwrap();
+        shell.set_var("name", "x").unwrap();
+
+        let args = expand(
+            "echo ${unset_var-a} ${empty-b} ${empty:-c} ${name:+d} ${empty+e} ${empty:+f}",
+            &mut shell,
+        );
+        assert_eq!(args.unwrap(), vec!["echo", "a", "c", "d", "e"]);
+
+        let args = expand("echo \"${unset_var:-$name and $name}\"", &mut shell);
+        assert_eq!(args.unwrap(), vec!["echo", "x and x"]);
+    }
+
+    #[test]
+    fn test_expand_assign_and_error() {
+        let mut shell = Shell::new();
+
+        let args = expand("echo ${new_var:=assigned} $new_var", &mut shell);
+        assert_eq!(args.unwrap(), vec!["echo", "assigned", "assigned"]);
+        assert!(expand("echo ${1:=x}", &mut shell).is_err());
+
+        let err = expand("echo ${unset_var:?is missing}", &mut shell).unwrap_err();
+        assert_eq!(err.to_string(), "unset_var: is missing");
+        let err = expand("echo ${unset_var?}", &mut shell).unwrap_err();
+        assert_eq!(err.to_string(), "unset_var: parameter null or not set");
+    }
+
+    #[test]
+    fn test_expand_string_operators() {
+        let mut shell = Shell::new();
+        shell.set_var("path", "/usr/local/lib.tar.gz").unwrap();
+        shell.set_var("star", "*").unwrap();
+
+        let args = expand(
+            "echo ${#path} ${path#*/} ${path##*/} ${path%.*} ${path%%.*} ${path#\"$star\"}",
+            &mut shell,
+        );
+        assert_eq!(
+            args.unwrap(),
+            vec![
+                "echo",
+                "21",
+                "usr/local/lib.tar.gz",
+                "lib.tar.gz",
+                "/usr/local/lib.tar",
+                "/usr/local/lib",
+                "/usr/local/lib.tar.gz"
+            ]
+        );
+
+        let args = expand(
+            "echo ${path/l/L} ${path//l/L} ${path//[.\\/]} \"${path/\\/usr/a b}\"",
+            &mut shell,
+        );
+        assert_eq!(
+            args.unwrap(),
+            vec![
+                "echo",
+                "/usr/Local/lib.tar.gz",
+                "/usr/LocaL/Lib.tar.gz",
+                "usrlocallibtargz",
+                "a b/local/lib.tar.gz"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_expand_extended_patterns() {
+        let mut shell = Shell::new();
+        shell.set_var("file", "lib.tar.gz").unwrap();
+        let input = "echo ${file%%.+(gz|tar|.)} ${file/!(*.*)/x} ${file//@(a|z)/_}";
+
+        let args = expand(input, &mut shell);
+        assert_eq!(args.unwrap(), vec!["echo", "lib.tar.gz", "lib.tar.gz", "lib.tar.gz"]);
+        shell.set_option("extglob", true).unwrap();
+        let args = expand(input, &mut shell);
+        assert_eq!(args.unwrap(), vec!["echo", "lib", "x.tar.gz", "lib.t_r.g_"]);
+    }
+
+    #[test]
+    fn test_expand_substring_and_case() {
+        let mut shell = Shell::new();
+        shell.set_var("word", "Hello World").unwrap();
+
+        let args = expand(
+            "echo ${word:6} ${word:0:5} ${word: -5:2} ${word:2:-6} ${word:20}",
+            &mut shell,
+        );
+        assert_eq!(args.unwrap(), vec!["echo", "World", "Hello", "Wo", "llo"]);
+        assert!(expand("echo ${word:5:-7}", &mut shell).is_err());
+
+        let args = expand(
+            "echo \"${word^^}\" \"${word,,}\" \"${word,}\" \"${word^^[lo]}\"",
+            &mut shell,
+        );
+        assert_eq!(
+            args.unwrap(),
+            vec![
+                "echo",
+                "HELLO WORLD",
+                "hello world",
+                "hello World",
+                "HeLLO WOrLd"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_expand_special_parameters() {
+        let mut shell = Shell::new();
+        shell.last_status = 3;
+        shell.positional = vec!["a".to_string(), "b".to_string()];
+
+        let args = expand("echo $? $# $1 ${2} $3 $12", &mut shell);
+        assert_eq!(args.unwrap(), vec!["echo", "3", "2", "a", "b", "a2"]);
+    }
+
+    #[test]
+    fn test_expand_quoted_positional_parameters() {
+        let mut shell = Shell::new();
+        shell.positional = vec!["a b".to_string(), "".to_string(), "c".to_string()];
+
+        let args = expand("echo \"<$@>\" $@ \"$*\"", &mut shell);
+        assert_eq!(
+            args.unwrap(),
+            vec!["echo", "<a b", "", "c>", "a", "b", "c", "a b  c"]
+        );
+        shell.set_var("IFS", ",;").unwrap();
+        assert_eq!(expand("echo \"$*\"", &mut shell).unwrap(), vec!["echo", "a b,,c"]);
+        shell.set_var("IFS", "").unwrap();
+        assert_eq!(expand("echo \"$*\"", &mut shell).unwrap(), vec!["echo", "a bc"]);
+        shell.unset_var("IFS").unwrap();
+
+        shell.positional.clear();
+        let args = expand("echo \"$@\" \"x$@\" \"\"", &mut shell);
+        assert_eq!(args.unwrap(), vec!["echo", "x", ""]);
+    }
+
+    #[test]
+    fn test_expand_braces() {
+        let mut shell = Shell::new();
+        shell.set_var("x", "/a").unwrap();
+
+        let args = expand("echo {$x,y} pre{1..3}post \"{a,b}\" {a,\\ b}", &mut shell);
+        assert_eq!(
+            args.unwrap(),
+            vec!["echo", "/a", "y", "pre1post", "pre2post", "pre3post", "{a,b}", "a", " b"]
+        );
+    }
+
+    #[test]
+    fn test_expand_field_splitting() {
+        let mut shell = Shell::new();
+        shell.set_var("x", " a  b ").unwrap();
+        shell.set_var("y", "1,,2, 3").unwrap();
+        shell.set_var("e", "").unwrap();
+
+        let args = expand("echo $x \"$x\" a$x$(echo c)", &mut shell);
+        assert_eq!(
+            args.unwrap(),
+            vec!["echo", "a", "b", " a  b ", "a", "a", "b", "c"]
+        );
+
+        shell.set_var("IFS", ",").unwrap();
+        let args = expand("echo $y x$e\"\" \"$e\" $e", &mut shell);
+        assert_eq!(args.unwrap(), vec!["echo", "1", "", "2", " 3", "x", ""]);
+        shell.set_var("IFS", " ,").unwrap();
+        let args = expand("echo $y", &mut shell);
+        assert_eq!(args.unwrap(), vec!["echo", "1", "", "2", "3"]);
+        shell.set_var("IFS", "").unwrap();
+        let args = expand("echo $x", &mut shell);
+        assert_eq!(args.unwrap(), vec!["echo", " a  b "]);
+    }
+
+    #[test]
+    fn test_split_fields() {
+        assert_eq!(split_fields(" a \t b\n", None, None), vec!["a", "b"]);
+        assert_eq!(split_fields("a::b:", Some(":"), None), vec!["a", "", "b"]);
+        assert!(split_fields("  ", None, None).is_empty());
+        assert_eq!(
+            split_fields("  1 : 2  3 4  ", Some(" :"), Some(3)),
+            vec!["1", "2", "3 4"]
+        );
+        assert_eq!(split_fields("a b", Some(""), Some(1)), vec!["a b"]);
+    }
+}
@@ -0,0 +1,232 @@
+use crate::pattern::{has_wildcards, Pattern};
+use std::fs;
+use std::path::Path;
+
+/// Options that influence which paths a pattern matches.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GlobOptions {
+    /// Wildcards also match a leading `.` of a file name.
+    pub dotglob: bool,
+    /// `**` as a whole path component matches any number of directories.
+    pub globstar: bool,
+    /// Letters match regardless of their case.
+    pub nocaseglob: bool,
+    /// Extended patterns like `@(a|b)` are recognized.
+    pub extglob: bool,
+}
+
+/// Returns the sorted paths that match the pattern. Every component of the
+/// path is matched separately; `/` has to be matched explicitly. Relative
+/// patterns are matched in `cwd`, the paths stay relative.
+pub fn glob(pattern: &str, cwd: &Path, options: &GlobOptions) -> Vec<String> {
+    let (mut paths, components) = match pattern.strip_prefix('/') {
+        Some(relative) => (vec!["/".to_string()], relative),
+        None => (vec![String::new()], pattern),
+    };
+    let components: Vec<&str> = components.split('/').collect();
+    let last_idx = components.len() - 1;
+
+    for (idx, component) in components.iter().enumerate() {
+        let mut next_paths = vec![];
+        for path in &paths {
+            if idx == last_idx && component.is_empty() {
+                // A trailing slash only matches directories
+                if cwd.join(or_dot(path)).is_dir() {
+                    next_paths.push(path.clone());
+                }
+            } else if options.globstar && *component == "**" {
+                if idx == last_idx {
+                    walk(path, cwd, options, false, &mut next_paths);
+                } else {
+                    next_paths.push(path.clone());
+                    walk(path, cwd, options, true, &mut next_paths);
+                }
+            } else if has_wildcards(component, options.extglob) {
+                next_paths.extend(
+                    matching_entries(path, cwd, component, options)
+                        .into_iter()
+                        .map(|name| join(path, &name, idx < last_idx)),
+                );
+            } else {
+                let name = unescape(component);
+                let next_path = join(path, &name, idx < last_idx);
+                if cwd.join(&next_path).symlink_metadata().is_ok() {
+                    next_paths.push(next_path);
+                }
+            }
+        }
+        paths = next_paths;
+    }
+
+    paths.sort();
+    paths
+}
+
+/// Returns the names of the entries in `dir` that match `component`.
+fn matching_entries(dir: &str, cwd: &Path, component: &str, options: &GlobOptions) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(cwd.join(or_dot(dir))) else {
+        return vec![];
+    };
+    let pattern = Pattern::with_extglob(component, options.extglob).ignore_case(options.nocaseglob);
+    let explicit_dot = component.starts_with('.') || component.starts_with("\\.");
+
+    entries
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .filter(|name| !name.starts_with('.') || explicit_dot || options.dotglob)
+        .filter(|name| pattern.matches(name))
+        .collect()
+}
+
+/// Collects all entries below `dir` recursively. With `dirs_only` set, only
+/// directories are collected, each with a trailing `/`. Symbolic links to
+/// directories are not followed.
+fn walk(dir: &str, cwd: &Path, options: &GlobOptions, dirs_only: bool, paths: &mut Vec<String>) {
+    let Ok(entries) = fs::read_dir(cwd.join(or_dot(dir))) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+        if name.starts_with('.') && !options.dotglob {
+            continue;
+        }
+        let is_dir = entry.file_type().is_ok_and(|file_type| file_type.is_dir());
+        if is_dir || !dirs_only {
+            paths.push(join(dir, &name, dirs_only));
+        }
+        if is_dir {
+            walk(&join(dir, &name, true), cwd, options, dirs_only, paths);
+        }
+    }
+}
+
+/// Appends `name` to `path`. Intermediate components are separated by `/`.
+fn join(path: &str, name: &str, has_more: bool) -> String {
+    let mut joined = format!("{path}{name}");
+    if has_more {
+        joined.push('/');
+    }
+    joined
+}
+
+/// The current directory is the empty path while a pattern is matched.
+fn or_dot(path: &str) -> &str {
+    if path.is_empty() {
+        "."
+    } else {
+        path
+    }
+}
+
+fn unescape(component: &str) -> String {
+    let mut text = String::new();
+    let mut chars = component.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' => text.extend(chars.next()),
+            _ => text.push(ch),
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern;
+    use std::env;
+
+    fn create_tree(name: &str, files: &[&str]) -> String {
+        let root = env::temp_dir().join(format!("{name}_{}", std::process::id()));
+        for file in files {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+        root.display().to_string()
+    }
+
+    #[test]
+    fn test_glob() {
+        let root = create_tree(
+            "glob",
+            &[
+                "b.rs",
+                "a.rs",
+                ".hidden.rs",
+                "A.RS",
+                "src/main.rs",
+                "src/x.txt",
+            ],
+        );
+        let options = GlobOptions::default();
+
+        let paths = glob(&format!("{root}/*.rs"), Path::new("/"), &options);
+        assert_eq!(paths, vec![format!("{root}/a.rs"), format!("{root}/b.rs")]);
+        let paths = glob(&format!("{root}/*/*.rs"), Path::new("/"), &options);
+        assert_eq!(paths, vec![format!("{root}/src/main.rs")]);
+        let paths = glob(&format!("{root}/s*/"), Path::new("/"), &options);
+        assert_eq!(paths, vec![format!("{root}/src/")]);
+        let paths = glob(&format!("{root}/.*"), Path::new("/"), &options);
+        assert_eq!(paths, vec![format!("{root}/.hidden.rs")]);
+        assert!(glob(&format!("{root}/*.none"), Path::new("/"), &options).is_empty());
+        assert!(glob(&format!("{root}/{}", pattern::escape("*.rs")), Path::new("/"), &options).is_empty());
+
+        let options = GlobOptions {
+            dotglob: true,
+            nocaseglob: true,
+            ..Default::default()
+        };
+        let paths = glob(&format!("{root}/?.rs"), Path::new("/"), &options);
+        assert_eq!(
+            paths,
+            vec![
+                format!("{root}/A.RS"),
+                format!("{root}/a.rs"),
+                format!("{root}/b.rs")
+            ]
+        );
+        assert_eq!(glob(&format!("{root}/*.rs"), Path::new("/"), &options).len(), 4);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_globstar() {
+        let root = create_tree(
+            "globstar",
+            &[
+                "a.rs",
+                "src/b.rs",
+                "src/bin/c.rs",
+                "src/bin/d.txt",
+                ".git/e.rs",
+            ],
+        );
+        let options = GlobOptions {
+            globstar: true,
+            extglob: true,
+            ..Default::default()
+        };
+
+        let paths = glob(&format!("{root}/**/*.rs"), Path::new("/"), &options);
+        let expected = ["a.rs", "src/b.rs", "src/bin/c.rs"].map(|path| format!("{root}/{path}"));
+        assert_eq!(paths, expected);
+        let paths = glob(&format!("{root}/src/**"), Path::new("/"), &options);
+        let expected = ["src/b.rs", "src/bin", "src/bin/c.rs", "src/bin/d.txt"]
+            .map(|path| format!("{root}/{path}"));
+        assert_eq!(paths, expected);
+        let paths = glob(&format!("{root}/**/"), Path::new("/"), &options);
+        let expected = ["", "src/", "src/bin/"].map(|path| format!("{root}/{path}"));
+        assert_eq!(paths, expected);
+        let paths = glob(&format!("{root}/**/!(*.rs)"), Path::new("/"), &options);
+        let expected = ["src", "src/bin", "src/bin/d.txt"].map(|path| format!("{root}/{path}"));
+        assert_eq!(paths, expected);
+
+        let paths = glob(&format!("{root}/**/*.rs"), Path::new("/"), &GlobOptions::default());
+        assert_eq!(paths, vec![format!("{root}/src/b.rs")]);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -1,8 +1,14 @@
 use std::fs::File;
-use std::io::{BufRead, BufReader, Write};
+use std::io::{self, BufRead, BufReader, Write};
 use std::ops::Index;
+use std::path::Path;
 use anyhow::{anyhow, Result};
 
+/// Starts a line of a history file that announces an entry of several
+/// lines, followed by the number of lines, e.g. `#+3`.
+const MULTI_LINE_MARKER: &str = "#+";
+
+#[derive(Clone)]
 pub struct History {
     saved_entries: Vec<String>,
     unsaved_entries: Vec<String>,
@@ -20,37 +26,44 @@ impl History {
         self.unsaved_entries.push(entry);
     }
 
-    pub fn load(&mut self, path: &str) -> Result<()> {
+    /// Loads the entries of a history file, one per line. Only the lines
+    /// announced by a marker line form a single entry.
+    pub fn load(&mut self, path: impl AsRef<Path>) -> Result<()> {
         let file = File::open(path)?;
-        let reader = BufReader::new(file);
-
-        for line in reader.lines() {
-            match line {
-                Ok(line) => {
-                    if !line.trim().is_empty() {
-                        self.unsaved_entries.push(line);
-                    }
+        let mut lines = BufReader::new(file).lines();
+
+        while let Some(line) = lines.next() {
+            let line = line.map_err(|err| anyhow!("Error reading history file: {}", err))?;
+            match marked_line_count(&line) {
+                Some(count) => {
+                    let entry_lines = lines
+                        .by_ref()
+                        .take(count)
+                        .collect::<io::Result<Vec<_>>>()
+                        .map_err(|err| anyhow!("Error reading history file: {}", err))?;
+                    self.unsaved_entries.push(entry_lines.join("\n"));
                 }
-                Err(err) => return Err(anyhow!("Error reading history file: {}", err)),
+                None if line.trim().is_empty() => {}
+                None => self.unsaved_entries.push(line),
             }
         }
         Ok(())
     }
 
-    pub fn save(&mut self, path: &str) -> Result<()> {
+    pub fn save(&mut self, path: impl AsRef<Path>) -> Result<()> {
         let mut file = File::create(path)?;
         self.saved_entries.append(&mut self.unsaved_entries);
         self.unsaved_entries.clear();
         for entry in &self.saved_entries {
-            writeln!(file, "{}", entry)?;
+            write_entry(&mut file, entry)?;
         }
         Ok(())
     }
 
-    pub fn append(&mut self, path: &str) -> Result<()> {
+    pub fn append(&mut self, path: impl AsRef<Path>) -> Result<()> {
         let mut file = File::options().append(true).open(path)?;
         for entry in &self.unsaved_entries {
-            writeln!(file, "{}", entry)?;
+            write_entry(&mut file, entry)?;
         }
         self.saved_entries.append(&mut self.unsaved_entries);
         self.unsaved_entries.clear();
@@ -72,8 +85,7 @@ impl History {
         let num_saved = self.saved_entries.len();
 
         if remaining <= num_saved {
-            return self.saved_entries[num_saved - remaining..].to_vec()
-                .into_iter()
+            return self.saved_entries[num_saved - remaining..].iter().cloned()
                 .chain(self.unsaved_entries.clone())
                 .collect();
         }
@@ -89,6 +101,20 @@ impl History {
     }
 }
 
+/// Writes an entry as a line of a history file. An entry of several lines,
+/// or one that could be mistaken for a marker, follows a marker line.
+fn write_entry(file: &mut File, entry: &str) -> io::Result<()> {
+    if entry.contains('\n') || marked_line_count(entry).is_some() {
+        writeln!(file, "{MULTI_LINE_MARKER}{}", entry.split('\n').count())?;
+    }
+    writeln!(file, "{}", entry)
+}
+
+/// Returns the number of lines a marker line announces.
+fn marked_line_count(line: &str) -> Option<usize> {
+    line.strip_prefix(MULTI_LINE_MARKER)?.parse().ok()
+}
+
 impl Index<usize> for History {
     type Output = String;
 
@@ -99,4 +125,42 @@ impl Index<usize> for History {
             &self.unsaved_entries[index - self.saved_entries.len()]
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_multi_line_entries_survive_the_history_file() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("history");
+        let path = path.to_str().unwrap();
+        let entries = ["echo \\\\", "for i in 1 2\ndo\n\necho $i\ndone", "echo 'a\nb'", "cat <<EOF\n\nEOF", "#+2"];
+
+        let mut history = History::new();
+        for entry in entries {
+            history.add_entry(entry.to_string());
+        }
+        history.save(path).unwrap();
+        let content = std::fs::read_to_string(path).unwrap();
+        assert!(content.starts_with("echo \\\\\n#+5\nfor i in 1 2\ndo\n"));
+
+        let mut loaded = History::new();
+        loaded.load(path).unwrap();
+        assert_eq!(loaded.get_all_entries(), entries);
+    }
+
+    #[test]
+    fn test_malformed_lines_stay_single_entries() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("history");
+        std::fs::write(&path, "echo 'open\necho \\\nls\n#+2\nfor i in 1\ndo :; done\npwd\n").unwrap();
+
+        let mut history = History::new();
+        history.load(&path).unwrap();
+        let entries = ["echo 'open", "echo \\", "ls", "for i in 1\ndo :; done", "pwd"];
+        assert_eq!(history.get_all_entries(), entries);
+    }
+}
@@ -1,6 +1,7 @@
-use crate::arg_parse::ArgParser;
+use crate::arg_parse::{ArgParser, IncompleteInput, ParseError};
 use crate::cmd::ExecResult;
 use crate::read_line::read_line;
+use crate::redirect::RedirectionInfo;
 use anyhow::Result;
 use std::collections::HashSet;
 use std::env;
@@ -8,62 +9,104 @@ use std::fs::{read_dir, DirEntry};
 use std::io::{self, Write};
 use std::os::unix::fs::PermissionsExt;
 use std::path::PathBuf;
-use crate::history::History;
+use crate::shell::Shell;
 
 mod arg_parse;
+mod arith;
+mod ast;
+mod brace;
 mod cmd;
+mod expand;
+mod glob;
 mod read_line;
 mod redirect;
+mod shell;
+mod tilde;
 
 mod history;
+mod pattern;
 
 const PROMPT: &str = "$ ";
+/// Prompt for continuation lines if `PS2` is unset.
+const CONTINUATION_PROMPT: &str = "> ";
 
 pub fn repl() -> i32 {
-    let mut history = History::new();
-    let history_file_path = env::var("HISTFILE").unwrap_or("".to_string());
+    let mut shell = Shell::new();
+    let history_file_path = shell.get_parameter("HISTFILE").unwrap_or_default();
     if !history_file_path.is_empty() {
-        if let Err(err) = history.load(&history_file_path) {
+        if let Err(err) = shell.history.load(&history_file_path) {
             eprintln!("Error loading history: {}", err);
         }
     }
 
     loop {
-        print!("{}", PROMPT);
-        io::stdout().flush().unwrap();
-
         // Wait for user input
-        let input = read_line(PROMPT, command_completion, &history);
-        history.add_entry(input.clone());
+        let input = read_input(&shell);
+        shell.history.add_entry(input.clone());
 
-        match handle_input(&input, &mut history) {
+        match handle_input(&input, &mut shell) {
             Ok(exec_result) => match exec_result {
                 ExecResult::Exit(code) => {
                     if !history_file_path.is_empty() {
-                        if let Err(err) = history.save(&history_file_path) {
+                        if let Err(err) = shell.history.save(&history_file_path) {
                             eprintln!("Error saving history: {}", err);
                         }
                     }
                     return code
                 },
-                ExecResult::Continue => continue,
+                _ => continue,
             },
-            Err(msg) => eprintln!("{}", msg),
+            Err(err) => match err.downcast_ref::<ParseError>() {
+                Some(ParseError::EmptyInput) => continue,
+                Some(parse_error) => eprintln!("{}", parse_error.render(&input)),
+                None => eprintln!("{}", err),
+            },
+        }
+    }
+}
+
+/// Reads lines until they form a complete command, e.g. including the
+/// bodies of all here-documents, the closing quote or the command following
+/// a trailing `|`. Continuation lines are prompted for with `PS2`.
+fn read_input(shell: &Shell) -> String {
+    let mut input = String::new();
+    let mut prompt = PROMPT.to_string();
+
+    loop {
+        print!("{}", prompt);
+        io::stdout().flush().unwrap();
+        input.push_str(&read_line(&prompt, &|prefix| command_completion(prefix, shell), &shell.history));
+
+        match new_parser(shell).parse_args(&input) {
+            Err(err) if err.is::<IncompleteInput>() => {
+                input.push('\n');
+                prompt = shell
+                    .get_parameter("PS2")
+                    .unwrap_or_else(|| CONTINUATION_PROMPT.to_string());
+            }
+            _ => return input,
         }
     }
 }
 
-fn handle_input(input: &str, history: &mut History) -> Result<ExecResult> {
-    let commands = ArgParser::new().parse_args(input)?;
-    cmd::run_commands(&commands, history)
+fn handle_input(input: &str, shell: &mut Shell) -> Result<ExecResult> {
+    let commands = new_parser(shell).parse_args(input)?;
+    cmd::run_commands(&commands, &RedirectionInfo::new(), shell)
+}
+
+fn new_parser(shell: &Shell) -> ArgParser {
+    ArgParser::new()
+        .comments(shell.is_option_set("interactive_comments"))
+        .extglob(shell.is_option_set("extglob"))
+        .aliases(shell.aliases.clone())
 }
 
-fn get_executables() -> HashSet<String> {
+fn get_executables(shell: &Shell) -> HashSet<String> {
     let mut ret = HashSet::new();
 
-    if let Ok(path_var) = env::var("PATH") {
+    if let Some(path_var) = shell.get_parameter("PATH") {
         for path in env::split_paths(&path_var) {
-            for exec in get_executables_in_path(&path) {
+            for exec in get_executables_in_path(&shell.resolve_path(path)) {
                 ret.insert(exec);
             }
         }
@@ -77,7 +120,7 @@ fn get_executables() -> HashSet<String> {
 fn get_executables_in_path(path: &PathBuf) -> HashSet<String> {
     let mut ret = HashSet::new();
 
-    if let Ok(entries) = read_dir(&path) {
+    if let Ok(entries) = read_dir(path) {
         for entry in entries {
             if entry.is_err() {
                 continue;
@@ -103,10 +146,10 @@ fn is_executable(entry: &DirEntry) -> bool {
     }
 }
 
-fn command_completion(prefix: &str) -> Vec<String> {
+fn command_completion(prefix: &str, shell: &Shell) -> Vec<String> {
     let mut matched_commands = vec![];
 
-    for cmd in cmd::get_builtin_commands().union(&get_executables()) {
+    for cmd in cmd::get_builtin_commands().union(&get_executables(shell)) {
         if cmd.starts_with(prefix) {
             matched_commands.push(cmd.clone());
         }
@@ -119,32 +162,381 @@ fn command_completion(prefix: &str) -> Vec<String> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use tempfile::TempDir;
+
+    /// Runs `input` in `shell` with its stdout going to a temporary file
+    /// and returns what was written there.
+    fn run_and_capture(shell: &mut Shell, input: &str) -> String {
+        let dir = TempDir::new().unwrap();
+        let out_path = dir.path().join("out");
+        let input = format!("{{\n{input}\n}} > {}", out_path.display());
+        handle_input(&input, shell).unwrap();
+        std::fs::read_to_string(out_path).unwrap()
+    }
 
     #[test]
     fn handle_input_pipe() {
         let input = "tail -f README.md | head -n 5";
-        let result = handle_input(input, &mut History::new());
+        let result = handle_input(input, &mut Shell::new());
         assert!(result.is_ok());
     }
 
     #[test]
     fn handle_input_out_redir() {
         let input = "ls -l  >> /dev/null";
-        let result = handle_input(input, &mut History::new());
+        let result = handle_input(input, &mut Shell::new());
         assert!(result.is_ok());
     }
 
     #[test]
     fn handle_input_error_redir() {
         let input = "ls -l nonexistent 2>> /dev/null";
-        let result = handle_input(input, &mut History::new());
+        let result = handle_input(input, &mut Shell::new());
         assert!(result.is_ok());
     }
 
     #[test]
     fn handle_input_builtin_w_pipe() {
         let input = "echo pineapple-grape | wc";
-        let result = handle_input(input, &mut History::new());
+        let result = handle_input(input, &mut Shell::new());
         assert!(result.is_ok());
     }
+
+    #[test]
+    fn handle_input_builtin_outlives_reader() {
+        let input = "{ { echo {1..20000}; echo $? >&3; } | true; } 3>&1";
+        let content = run_and_capture(&mut Shell::new(), input);
+        assert_eq!(content, "141 \n");
+    }
+
+    #[test]
+    fn handle_input_loop_outlives_reader() {
+        let mut shell = Shell::new();
+        let input = "while true; do echo y; done | head -2; for i in {1..100000}; do echo $i; done | head -1";
+        let content = run_and_capture(&mut shell, input);
+        assert_eq!(content, "y \ny \n1 \n");
+        let input = "{ { for ((;;)); do echo y; done; echo $? >&3; } | true; } 3>&1";
+        assert_eq!(run_and_capture(&mut shell, input), "141 \n");
+    }
+
+    #[test]
+    fn handle_input_command_list() {
+        let input = "ls nonexistent 2> /dev/null && exit 3 || exit 4";
+        let result = handle_input(input, &mut Shell::new()).unwrap();
+        assert_eq!(result, ExecResult::Exit(4));
+
+        let input = "true && exit 5; exit 6";
+        let result = handle_input(input, &mut Shell::new()).unwrap();
+        assert_eq!(result, ExecResult::Exit(5));
+    }
+
+    #[test]
+    fn handle_input_in_redir() {
+        let input = "wc -l < README.md > /dev/null";
+        let result = handle_input(input, &mut Shell::new());
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn handle_input_fd_redir() {
+        let dir = TempDir::new().unwrap();
+        let log_path = dir.path().join("fd_redir.log");
+        let input = format!("sh -c 'cat <&4 >&3' 3>{} 4<Cargo.toml", log_path.display());
+        let result = handle_input(&input, &mut Shell::new());
+        assert!(result.is_ok());
+
+        let logged = std::fs::read_to_string(&log_path).unwrap();
+        assert_eq!(logged, std::fs::read_to_string("Cargo.toml").unwrap());
+    }
+
+    #[test]
+    fn handle_input_dup_redir_order() {
+        let dir = TempDir::new().unwrap();
+        let out_path = dir.path().join("out");
+        let out_path = out_path.display();
+
+        let input = format!("ls nonexistent > {out_path} 2>&1");
+        handle_input(&input, &mut Shell::new()).unwrap();
+        let with_errors = std::fs::read_to_string(out_path.to_string()).unwrap();
+
+        let input = format!("ls nonexistent 2>&1 > {out_path} | wc -l > /dev/null");
+        handle_input(&input, &mut Shell::new()).unwrap();
+        let without_errors = std::fs::read_to_string(out_path.to_string()).unwrap();
+
+        assert!(with_errors.contains("nonexistent"));
+        assert!(without_errors.is_empty());
+    }
+
+    #[test]
+    fn handle_input_failed_expansion_skips_redirects() {
+        let dir = TempDir::new().unwrap();
+        let out_path = dir.path().join("out");
+        std::fs::write(&out_path, "kept\n").unwrap();
+
+        let mut shell = Shell::new();
+        let input = format!("{{ echo $(( 1/0 )) > {}; }} 2> /dev/null", out_path.display());
+        handle_input(&input, &mut shell).unwrap();
+        assert_eq!(shell.last_status, 1);
+        assert_eq!(std::fs::read_to_string(&out_path).unwrap(), "kept\n");
+    }
+
+    #[test]
+    fn handle_input_here_documents() {
+        let input = "cat <<EOF; cat <<< 'here string'\nfirst\nsecond\nEOF";
+        let content = run_and_capture(&mut Shell::new(), input);
+        assert_eq!(content, "first\nsecond\nhere string\n");
+    }
+
+    #[test]
+    fn handle_input_parameter_expansion() {
+        let input = "ls nonexistent 2> /dev/null; cat <<EOF\nstatus $? ${unset_var:-default}\nEOF";
+        let content = run_and_capture(&mut Shell::new(), input);
+        assert_eq!(content, "status 2 default\n");
+    }
+
+    #[test]
+    fn handle_input_command_substitution() {
+        let input = r#"cat <<< "$(echo a; printf 'b\n\n') `echo $(echo nested) | tr a-z A-Z`""#;
+        let content = run_and_capture(&mut Shell::new(), input);
+        assert_eq!(content, "a \nb NESTED \n");
+    }
+
+    #[test]
+    fn handle_input_arithmetic() {
+        let mut shell = Shell::new();
+        let input = r#"let i=3 'j = i << 2'; ((i++)) && ((k = i * j)); cat <<< "$((k % 10)),$? $((0x10 + 2#11))""#;
+        let content = run_and_capture(&mut shell, input);
+        assert_eq!(content, "8,0 19\n");
+
+        handle_input("((0))", &mut shell).unwrap();
+        assert_eq!(shell.last_status, 1);
+        handle_input("let 'x = 1 / 0' 2> /dev/null", &mut shell).unwrap();
+        assert_eq!(shell.last_status, 1);
+    }
+
+    #[test]
+    fn handle_input_globbing() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path();
+        for file in ["b.txt", "a.txt", ".c.txt"] {
+            std::fs::write(root.join(file), "").unwrap();
+        }
+        let root = root.display();
+
+        let mut shell = Shell::new();
+        let input = format!("printf '%s\\n' {root}/*.txt '{root}'/?.t* \"{root}/*.txt\" {root}/*.none");
+        let content = run_and_capture(&mut shell, &input);
+        assert_eq!(
+            content,
+            format!(
+                "{root}/a.txt\n{root}/b.txt\n{root}/a.txt\n{root}/b.txt\n{root}/*.txt\n{root}/*.none\n"
+            )
+        );
+
+        let input = format!("shopt -s nullglob dotglob; printf '%s\\n' {root}/*.t*t {root}/*.none");
+        let content = run_and_capture(&mut shell, &input);
+        assert_eq!(content, format!("{root}/.c.txt\n{root}/a.txt\n{root}/b.txt\n"));
+
+        let input = format!("shopt -s failglob; echo {root}/*.none 2> /dev/null");
+        handle_input(&input, &mut shell).unwrap();
+        assert_eq!(shell.last_status, 1);
+    }
+
+    #[test]
+    fn handle_input_if_and_case() {
+        let mut shell = Shell::new();
+        let input = "if true; then\n\
+                     if false; then echo a; elif true; then echo b; fi\n\
+                     case abc.rs in *.txt) echo txt;; *.rs|*.c) echo src;& x) echo x;; esac\n\
+                     case ab in a*) echo 1;;& b) echo 2;;& *b) echo 3;; esac\n\
+                     fi";
+        let content = run_and_capture(&mut shell, input);
+        assert_eq!(content, "b \nsrc \nx \n1 \n3 \n");
+
+        handle_input("if false; then true; fi", &mut shell).unwrap();
+        assert_eq!(shell.last_status, 0);
+        handle_input("case a in b) true;; esac", &mut shell).unwrap();
+        assert_eq!(shell.last_status, 0);
+        handle_input("if true; then false; fi", &mut shell).unwrap();
+        assert_eq!(shell.last_status, 1);
+    }
+
+    #[test]
+    fn handle_input_loops() {
+        let mut shell = Shell::new();
+        let input = "for ((i = 0; i < 4; i++)); do\n\
+                     if ((i == 1)); then continue; fi\n\
+                     for x in a b c; do [ $x = b ] && continue 2; ((i == 3)) && break 2; echo $i$x; done\n\
+                     done\n\
+                     printf '1\\n2\\n' | while read n; do echo line $n; done\n\
+                     until ((i == 0)); do let i--; done; echo $i";
+        let content = run_and_capture(&mut shell, input);
+        assert_eq!(content, "0a \n2a \nline 1 \nline 2 \n0 \n");
+
+        handle_input("while false; do true; done", &mut shell).unwrap();
+        assert_eq!(shell.last_status, 0);
+        handle_input("break 2> /dev/null", &mut shell).unwrap();
+        assert_eq!(shell.last_status, 0);
+        assert_eq!(shell.loop_depth, 0);
+    }
+
+    #[test]
+    fn handle_input_subshells_and_groups() {
+        let dir = TempDir::new().unwrap();
+        std::fs::create_dir_all(dir.path().join("sub")).unwrap();
+        let root = dir.path().display();
+
+        let mut shell = Shell::new();
+        let cwd = env::current_dir().unwrap();
+        let input = format!(
+            "(cd {root}/sub && pwd); (exit 3); echo $?\n\
+             {{ echo a; echo b; }} | (cd {root}; cat > group.out)"
+        );
+        let content = run_and_capture(&mut shell, &input);
+        assert_eq!(env::current_dir().unwrap(), cwd);
+        assert_eq!(content, format!("{root}/sub\n3 \n"));
+        let content = std::fs::read_to_string(dir.path().join("group.out")).unwrap();
+        assert_eq!(content, "a \nb \n");
+    }
+
+    #[test]
+    fn handle_input_path_assignment() {
+        let dir = TempDir::new().unwrap();
+        let tool = dir.path().join("mytool");
+        std::fs::write(&tool, "#!/bin/sh\necho mytool ran\n").unwrap();
+        std::fs::set_permissions(&tool, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let mut shell = Shell::new();
+        let input = format!("PATH={}:$PATH mytool", dir.path().display());
+        assert_eq!(run_and_capture(&mut shell, &input), "mytool ran\n");
+        handle_input("mytool 2> /dev/null", &mut shell).unwrap();
+        assert_eq!(shell.last_status, 127);
+        handle_input("PATH=/nonexistent ls 2> /dev/null", &mut shell).unwrap();
+        assert_eq!(shell.last_status, 127);
+    }
+
+    #[test]
+    fn command_completion_uses_shell_path() {
+        let dir = TempDir::new().unwrap();
+        let tool = dir.path().join("zz_completion_tool");
+        std::fs::write(&tool, "").unwrap();
+        std::fs::set_permissions(&tool, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let mut shell = Shell::new();
+        let input = format!("export PATH={}", dir.path().display());
+        handle_input(&input, &mut shell).unwrap();
+        assert_eq!(command_completion("zz_comp", &shell), vec!["zz_completion_tool"]);
+        handle_input("unset PATH", &mut shell).unwrap();
+        assert!(command_completion("zz_comp", &shell).is_empty());
+    }
+
+    #[test]
+    fn handle_input_working_directory_of_subshells() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        std::fs::create_dir_all(root.join("sub")).unwrap();
+        std::fs::write(root.join("sub/a.txt"), "in sub\n").unwrap();
+
+        let mut shell = Shell::new();
+        let cwd = env::current_dir().unwrap();
+        let input = format!(
+            "cd {}; (cd sub; echo *) | cat; echo $(cd sub && pwd) $(pwd)\n\
+             cat < sub/a.txt; sh -c 'ls sub'",
+            root.display()
+        );
+        let content = run_and_capture(&mut shell, &input);
+        let root = root.display();
+        assert_eq!(content, format!("a.txt \n{root}/sub {root} \nin sub\na.txt\n"));
+        assert_eq!(env::current_dir().unwrap(), cwd);
+        assert_eq!(shell.get_parameter("PWD"), Some(root.to_string()));
+    }
+
+    #[test]
+    fn handle_input_aliases() {
+        let mut shell = Shell::new();
+        let input = "alias say='echo said' e='echo ' q=\"'it s' \" up='| tr a-z A-Z'";
+        handle_input(input, &mut shell).unwrap();
+        let content = run_and_capture(&mut shell, "say hi; e q up; alias say q; type say; unalias q; alias -p up");
+        let expected = [
+            "said hi ",
+            "IT S ",
+            "alias say='echo said'",
+            r"alias q=''\''it s'\'' '",
+            "say is aliased to `echo said'",
+            "alias e='echo '",
+            "alias say='echo said'",
+            "alias up='| tr a-z A-Z'",
+            "alias up='| tr a-z A-Z'",
+        ];
+        assert_eq!(content, expected.join("\n") + "\n");
+
+        handle_input("unalias -a; alias say 2> /dev/null", &mut shell).unwrap();
+        assert_eq!(shell.last_status, 1);
+        assert!(shell.aliases.is_empty());
+    }
+
+    #[test]
+    fn handle_input_functions() {
+        let mut shell = Shell::new();
+        shell.positional = vec!["top".to_string()];
+        let input = "show() { echo \"$# $1\" $x; }\n\
+                     function outer {\n\
+                     local x=$1\n\
+                     for arg in \"$@\"; do show \"$arg\"; [ $arg = b ] && return 3; done\n\
+                     }\n\
+                     outer 'a 1' b c; echo $? $1; show";
+        let content = run_and_capture(&mut shell, input);
+        assert_eq!(content, "1 a 1 a 1 \n1 b a 1 \n3 top \n0  \n");
+
+        handle_input("return 2> /dev/null", &mut shell).unwrap();
+        assert_eq!(shell.last_status, 1);
+        handle_input("f() { local 1=x 2> /dev/null; }; f", &mut shell).unwrap();
+        assert_eq!(shell.last_status, 1);
+    }
+
+    #[test]
+    fn handle_input_variables() {
+        let mut shell = Shell::new();
+        let input = "declare -i n=3+4; declare -u up=hi; export shared=1; declare private=2\n\
+                     readonly q='a\"$b'; f() { local n=x; declare -l lo=AB; echo $n $lo; }\n\
+                     { f; echo $n $up; sh -c 'echo ${shared-}${private-}'; declare -p q n lo; } 2>&1";
+        let content = run_and_capture(&mut shell, input);
+        let expected = [
+            "x ab ",
+            "7 HI ",
+            "1",
+            r#"declare -r q="a\"\$b""#,
+            r#"declare -i n="7""#,
+            "declare: lo: not found",
+        ];
+        assert_eq!(content, expected.join("\n") + "\n");
+
+        handle_input("unset q 2> /dev/null", &mut shell).unwrap();
+        assert_eq!(shell.last_status, 1);
+        handle_input("export -n shared; unset private n", &mut shell).unwrap();
+        assert!(!shell.variable("shared").unwrap().exported);
+        assert_eq!(shell.get_parameter("private"), None);
+        assert_eq!(shell.get_parameter("n"), None);
+    }
+
+    #[test]
+    fn handle_input_assignments() {
+        let mut shell = Shell::new();
+        let input = "x=1 y=\"a  b\"; show() { echo $x \"$y\" $tmp; }\n\
+                     tmp=2 sh -c 'echo ${tmp-unset}'; tmp=3 show; IFS=, read a b <<< 1,2; echo $a $b";
+        let content = run_and_capture(&mut shell, input);
+        assert_eq!(content, "2\n1 a  b 3 \n1 2 \n");
+        assert_eq!(shell.get_parameter("tmp"), None);
+        assert_eq!(shell.get_parameter("IFS"), None);
+
+        handle_input("v=$(exit 3)", &mut shell).unwrap();
+        assert_eq!(shell.last_status, 3);
+        handle_input("readonly v; v=1 2> /dev/null", &mut shell).unwrap();
+        assert_eq!(shell.last_status, 1);
+
+        // Special builtins keep the assignments in front of them
+        handle_input("a=1 export b=2; c=3 unset b; d=4 readonly e", &mut shell).unwrap();
+        let values = ["a", "b", "c", "d"].map(|name| shell.get_parameter(name));
+        assert_eq!(values, [Some("1".to_string()), None, Some("3".to_string()), Some("4".to_string())]);
+    }
 }
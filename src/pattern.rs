@@ -0,0 +1,449 @@
+/// A shell pattern with the wildcards `*`, `?`, bracket expressions like
+/// `[a-z]` and, if `extglob` is enabled, the extended patterns `?(...)`,
+/// `*(...)`, `+(...)`, `@(...)` and `!(...)` with alternatives separated by
+/// `|`. A backslash makes the following character match literally.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pattern {
+    tokens: Vec<Token>,
+    ignore_case: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Char(char),
+    /// `?`
+    AnyChar,
+    /// `*`
+    AnyString,
+    /// `[...]` or the negated `[!...]` and `[^...]`
+    Bracket {
+        negated: bool,
+        items: Vec<BracketItem>,
+    },
+    /// An extended pattern with its alternatives
+    Group {
+        kind: GroupKind,
+        alternatives: Vec<Vec<Token>>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum GroupKind {
+    /// `?(...)`: zero or one occurrence
+    ZeroOrOne,
+    /// `*(...)`: zero or more occurrences
+    ZeroOrMore,
+    /// `+(...)`: one or more occurrences
+    OneOrMore,
+    /// `@(...)`: exactly one occurrence
+    One,
+    /// `!(...)`: anything except one occurrence
+    Not,
+}
+
+impl GroupKind {
+    fn from_char(ch: char) -> Option<GroupKind> {
+        match ch {
+            '?' => Some(GroupKind::ZeroOrOne),
+            '*' => Some(GroupKind::ZeroOrMore),
+            '+' => Some(GroupKind::OneOrMore),
+            '@' => Some(GroupKind::One),
+            '!' => Some(GroupKind::Not),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum BracketItem {
+    Char(char),
+    Range(char, char),
+    /// A character class like `[:alpha:]`
+    Class(String),
+}
+
+impl Pattern {
+    pub fn new(pattern: &str) -> Pattern {
+        Self::with_extglob(pattern, false)
+    }
+
+    /// Parses a pattern in which extended patterns are recognized if
+    /// `extglob` is set, like with `shopt -s extglob`.
+    pub fn with_extglob(pattern: &str, extglob: bool) -> Pattern {
+        let chars: Vec<char> = pattern.chars().collect();
+        let mut pos = 0;
+        let tokens = Self::parse_sequence(&chars, &mut pos, false, extglob);
+
+        Pattern {
+            tokens,
+            ignore_case: false,
+        }
+    }
+
+    /// Parses tokens up to the end of the pattern or, within a group, up to
+    /// the next `|` or `)`.
+    fn parse_sequence(chars: &[char], pos: &mut usize, in_group: bool, extglob: bool) -> Vec<Token> {
+        let mut tokens = vec![];
+
+        while *pos < chars.len() {
+            let ch = chars[*pos];
+            if in_group && (ch == '|' || ch == ')') {
+                break;
+            }
+            if extglob && chars.get(*pos + 1) == Some(&'(') {
+                if let Some(kind) = GroupKind::from_char(ch) {
+                    if let Some(group) = Self::parse_group(chars, pos, kind) {
+                        tokens.push(group);
+                        continue;
+                    }
+                }
+            }
+            let token = match ch {
+                '*' => Token::AnyString,
+                '?' => Token::AnyChar,
+                '[' => match Self::parse_bracket(chars, *pos + 1) {
+                    Some((token, end)) => {
+                        tokens.push(token);
+                        *pos = end;
+                        continue;
+                    }
+                    None => Token::Char('['),
+                },
+                '\\' if *pos + 1 < chars.len() => {
+                    *pos += 1;
+                    Token::Char(chars[*pos])
+                }
+                ch => Token::Char(ch),
+            };
+            tokens.push(token);
+            *pos += 1;
+        }
+
+        tokens
+    }
+
+    /// Parses an extended pattern starting at its kind character. Returns
+    /// `None` if the parentheses are not balanced.
+    fn parse_group(chars: &[char], pos: &mut usize, kind: GroupKind) -> Option<Token> {
+        let mut end = *pos + 2;
+        let mut depth = 0;
+        loop {
+            match chars.get(end)? {
+                '\\' => end += 1,
+                '(' => depth += 1,
+                ')' if depth == 0 => break,
+                ')' => depth -= 1,
+                _ => {}
+            }
+            end += 1;
+        }
+
+        *pos += 2;
+        let mut alternatives = vec![];
+        loop {
+            alternatives.push(Self::parse_sequence(&chars[..end], pos, true, true));
+            if *pos >= end {
+                break;
+            }
+            *pos += 1;
+        }
+        *pos = end + 1;
+
+        Some(Token::Group { kind, alternatives })
+    }
+
+    /// Makes letters match regardless of their case.
+    pub fn ignore_case(mut self, ignore_case: bool) -> Pattern {
+        self.ignore_case = ignore_case;
+        self
+    }
+
+    /// Parses a bracket expression starting after `[`. Returns the token and
+    /// the position after the closing `]`, or `None` if there is none.
+    fn parse_bracket(chars: &[char], mut pos: usize) -> Option<(Token, usize)> {
+        let negated = matches!(chars.get(pos), Some('!' | '^'));
+        if negated {
+            pos += 1;
+        }
+        let mut items = vec![];
+        let first = pos;
+
+        loop {
+            let mut ch = *chars.get(pos)?;
+            if ch == ']' && pos > first {
+                return Some((Token::Bracket { negated, items }, pos + 1));
+            }
+            if ch == '[' && chars.get(pos + 1) == Some(&':') {
+                let rest: String = chars[pos + 2..].iter().collect();
+                if let Some(len) = rest.find(":]") {
+                    items.push(BracketItem::Class(rest[..len].to_string()));
+                    pos += 2 + rest[..len].chars().count() + 2;
+                    continue;
+                }
+            }
+            if ch == '\\' && pos + 1 < chars.len() {
+                pos += 1;
+                ch = chars[pos];
+            }
+            pos += 1;
+            match (chars.get(pos), chars.get(pos + 1)) {
+                (Some('-'), Some(&end)) if end != ']' => {
+                    items.push(BracketItem::Range(ch, end));
+                    pos += 2;
+                }
+                _ => items.push(BracketItem::Char(ch)),
+            }
+        }
+    }
+
+    /// Returns whether the pattern matches the whole text.
+    pub fn matches(&self, text: &str) -> bool {
+        let chars: Vec<char> = text.chars().collect();
+        self.matches_chars(&chars)
+    }
+
+    fn matches_chars(&self, chars: &[char]) -> bool {
+        self.match_tokens(&self.tokens, chars)
+    }
+
+    fn match_tokens(&self, tokens: &[Token], chars: &[char]) -> bool {
+        let Some((token, rest)) = tokens.split_first() else {
+            return chars.is_empty();
+        };
+        match token {
+            Token::AnyString => {
+                // Consecutive stars behave like a single one
+                if rest.first() == Some(&Token::AnyString) {
+                    return self.match_tokens(rest, chars);
+                }
+                (0..=chars.len()).any(|len| self.match_tokens(rest, &chars[len..]))
+            }
+            Token::Group { kind, alternatives } => (0..=chars.len()).any(|len| {
+                self.match_group(*kind, alternatives, &chars[..len])
+                    && self.match_tokens(rest, &chars[len..])
+            }),
+            _ => match chars.split_first() {
+                Some((ch, chars)) => {
+                    self.token_matches(token, *ch) && self.match_tokens(rest, chars)
+                }
+                None => false,
+            },
+        }
+    }
+
+    /// Returns whether an extended pattern matches exactly the given text.
+    fn match_group(&self, kind: GroupKind, alternatives: &[Vec<Token>], chars: &[char]) -> bool {
+        let matches_one = |chars: &[char]| {
+            alternatives
+                .iter()
+                .any(|alternative| self.match_tokens(alternative, chars))
+        };
+        match kind {
+            GroupKind::One => matches_one(chars),
+            GroupKind::ZeroOrOne => chars.is_empty() || matches_one(chars),
+            GroupKind::Not => !matches_one(chars),
+            GroupKind::ZeroOrMore | GroupKind::OneOrMore => {
+                if chars.is_empty() {
+                    return kind == GroupKind::ZeroOrMore || matches_one(chars);
+                }
+                // The first occurrence must not be empty to make progress
+                (1..=chars.len()).any(|len| {
+                    matches_one(&chars[..len])
+                        && (len == chars.len()
+                            || self.match_group(GroupKind::OneOrMore, alternatives, &chars[len..]))
+                })
+            }
+        }
+    }
+
+    fn token_matches(&self, token: &Token, ch: char) -> bool {
+        if token.matches(ch) {
+            return true;
+        }
+        self.ignore_case
+            && (ch.to_lowercase().any(|lower| token.matches(lower))
+                || ch.to_uppercase().any(|upper| token.matches(upper)))
+    }
+
+    /// Returns the length in characters of the shortest or longest prefix of
+    /// `chars` that matches the pattern.
+    pub fn match_prefix(&self, chars: &[char], longest: bool) -> Option<usize> {
+        candidate_lengths(chars.len(), longest).find(|len| self.matches_chars(&chars[..*len]))
+    }
+
+    /// Returns the length in characters of the shortest or longest suffix of
+    /// `chars` that matches the pattern.
+    pub fn match_suffix(&self, chars: &[char], longest: bool) -> Option<usize> {
+        candidate_lengths(chars.len(), longest)
+            .find(|len| self.matches_chars(&chars[chars.len() - len..]))
+    }
+}
+
+fn candidate_lengths(max_len: usize, longest: bool) -> Box<dyn Iterator<Item = usize>> {
+    if longest {
+        Box::new((0..=max_len).rev())
+    } else {
+        Box::new(0..=max_len)
+    }
+}
+
+impl Token {
+    fn matches(&self, ch: char) -> bool {
+        match self {
+            Token::Char(expected) => ch == *expected,
+            Token::AnyChar => true,
+            Token::AnyString | Token::Group { .. } => false,
+            Token::Bracket { negated, items } => {
+                items.iter().any(|item| item.matches(ch)) != *negated
+            }
+        }
+    }
+}
+
+impl BracketItem {
+    fn matches(&self, ch: char) -> bool {
+        match self {
+            BracketItem::Char(expected) => ch == *expected,
+            BracketItem::Range(first, last) => (*first..=*last).contains(&ch),
+            BracketItem::Class(class) => match class.as_str() {
+                "alnum" => ch.is_alphanumeric(),
+                "alpha" => ch.is_alphabetic(),
+                "blank" => ch == ' ' || ch == '\t',
+                "cntrl" => ch.is_control(),
+                "digit" => ch.is_ascii_digit(),
+                "graph" => ch.is_ascii_graphic(),
+                "lower" => ch.is_lowercase(),
+                "print" => ch.is_ascii_graphic() || ch == ' ',
+                "punct" => ch.is_ascii_punctuation(),
+                "space" => ch.is_whitespace(),
+                "upper" => ch.is_uppercase(),
+                "xdigit" => ch.is_ascii_hexdigit(),
+                _ => false,
+            },
+        }
+    }
+}
+
+/// Returns whether the pattern contains unescaped wildcards, i.e. might
+/// match something else than its literal text. Extended patterns only count
+/// if `extglob` is set.
+pub fn has_wildcards(pattern: &str, extglob: bool) -> bool {
+    let mut chars = pattern.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' => {
+                chars.next();
+            }
+            '*' | '?' | '[' => return true,
+            '+' | '@' | '!' if extglob && chars.peek() == Some(&'(') => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Escapes all characters of `text` that have a special meaning in patterns.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+    for ch in text.chars() {
+        if matches!(
+            ch,
+            '*' | '?' | '[' | ']' | '\\' | '+' | '@' | '!' | '(' | ')' | '|'
+        ) {
+            escaped.push('\\');
+        }
+        escaped.push(ch);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wildcards() {
+        assert!(Pattern::new("*.rs").matches("main.rs"));
+        assert!(Pattern::new("*.rs").matches(".rs"));
+        assert!(!Pattern::new("*.rs").matches("main.rsx"));
+        assert!(Pattern::new("a*b*c").matches("aXbYbZc"));
+        assert!(Pattern::new("?at").matches("cat"));
+        assert!(!Pattern::new("?at").matches("at"));
+        assert!(Pattern::new("").matches(""));
+        assert!(Pattern::new("**").matches("anything"));
+    }
+
+    #[test]
+    fn test_brackets() {
+        assert!(Pattern::new("[a-c]x").matches("bx"));
+        assert!(!Pattern::new("[!a-c]x").matches("bx"));
+        assert!(Pattern::new("[]a]").matches("]"));
+        assert!(Pattern::new("[[:digit:]_]*").matches("_1"));
+        assert!(!Pattern::new("[[:upper:]]").matches("a"));
+        assert!(Pattern::new("[abc").matches("[abc"));
+    }
+
+    fn extended(pattern: &str) -> Pattern {
+        Pattern::with_extglob(pattern, true)
+    }
+
+    #[test]
+    fn test_extended_patterns() {
+        assert!(extended("*.@(rs|toml)").matches("Cargo.toml"));
+        assert!(!extended("*.@(rs|toml)").matches("Cargo.lock"));
+        assert!(extended("a?(b|c)d").matches("ad"));
+        assert!(extended("a?(b|c)d").matches("acd"));
+        assert!(!extended("a?(b|c)d").matches("abcd"));
+        assert!(extended("a*(b|c)d").matches("abcbd"));
+        assert!(extended("a+(b|c)d").matches("abcbd"));
+        assert!(!extended("a+(b|c)d").matches("ad"));
+        assert!(extended("!(*.rs)").matches("main.c"));
+        assert!(!extended("!(*.rs)").matches("main.rs"));
+        assert!(extended("@(a|+([0-9]))x").matches("123x"));
+        assert!(extended("?(a").matches("?(a"));
+        assert!(extended(&escape("@(a)")).matches("@(a)"));
+    }
+
+    #[test]
+    fn test_extended_patterns_need_extglob() {
+        assert!(Pattern::new("@(a|b)").matches("@(a|b)"));
+        assert!(!Pattern::new("@(a|b)").matches("a"));
+        assert!(Pattern::new("!(x)").matches("!(x)"));
+        assert!(Pattern::new("?(x)").matches("a(x)"));
+    }
+
+    #[test]
+    fn test_has_wildcards() {
+        assert!(has_wildcards("*.rs", false));
+        assert!(has_wildcards("+(a|b)", true));
+        assert!(!has_wildcards("+(a|b)", false));
+        assert!(!has_wildcards("a+b@c!", true));
+        assert!(!has_wildcards(&escape("[a]*"), true));
+    }
+
+    #[test]
+    fn test_ignore_case() {
+        assert!(!Pattern::new("*.RS").matches("main.rs"));
+        assert!(Pattern::new("*.RS").ignore_case(true).matches("main.rs"));
+        assert!(Pattern::new("[a-c]").ignore_case(true).matches("B"));
+    }
+
+    #[test]
+    fn test_escaped_characters() {
+        assert!(Pattern::new("\\*").matches("*"));
+        assert!(!Pattern::new("\\*").matches("x"));
+        assert!(Pattern::new(&escape("a[1]?")).matches("a[1]?"));
+    }
+
+    #[test]
+    fn test_prefix_and_suffix() {
+        let chars: Vec<char> = "a/b/c".chars().collect();
+        let pattern = Pattern::new("*/");
+        assert_eq!(pattern.match_prefix(&chars, false), Some(2));
+        assert_eq!(pattern.match_prefix(&chars, true), Some(4));
+        let pattern = Pattern::new("/*");
+        assert_eq!(pattern.match_suffix(&chars, false), Some(2));
+        assert_eq!(pattern.match_suffix(&chars, true), Some(4));
+        assert_eq!(Pattern::new("x").match_prefix(&chars, false), None);
+    }
+}
@@ -4,9 +4,9 @@ use termion::input::TermRead;
 use termion::raw::{IntoRawMode, RawTerminal};
 use crate::history::History;
 
-pub type TabCompletion = fn (prefix: &str) -> Vec<String>;
+pub type TabCompletion<'a> = &'a dyn Fn(&str) -> Vec<String>;
 
-pub fn read_line(prompt: &str, tab_completion: TabCompletion, history: &History) -> String {
+pub fn read_line(prompt: &str, tab_completion: TabCompletion<'_>, history: &History) -> String {
     let mut buffer = String::new();
     {
         let mut stdout = stdout().into_raw_mode().unwrap();
@@ -112,7 +112,7 @@ fn show_matching_commands(
     stdout: &mut RawTerminal<Stdout>,
     prompt: &str,
     buffer: &str,
-    commands: &Vec<String>) {
+    commands: &[String]) {
     goto_begin_of_line(stdout, prompt, buffer);
     write!(
         stdout,
@@ -130,7 +130,7 @@ fn show_matching_commands(
     ).unwrap();
 }
 
-fn find_common_prefix(strings: &Vec<String>) -> String {
+fn find_common_prefix(strings: &[String]) -> String {
     let mut prefix: Vec<char> = vec![];
     let mut first = true;
 
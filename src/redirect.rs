@@ -1,63 +1,215 @@
+use std::collections::BTreeMap;
 use std::fmt::Debug;
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use std::fs::{File, OpenOptions};
-use std::io::Write;
+use std::io::{self, Write};
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::thread;
 
-#[derive(Debug)]
-pub struct RedirectionInfo {
-    stdout: Option<(String, FileOpenMode)>,
-    stderr: Option<(String, FileOpenMode)>,
-}
-
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum FileOpenMode {
     Create,
     Append,
+    Read,
+    ReadWrite,
+}
+
+/// What a file descriptor of a command refers to.
+#[derive(Debug, Clone)]
+pub enum FdTarget {
+    /// One of the shell's own standard streams.
+    Inherited(RawFd),
+    /// An open file or pipe end. Duplicated descriptors share the same file.
+    File(Arc<File>),
+}
+
+impl FdTarget {
+    fn try_clone_owned(&self) -> io::Result<OwnedFd> {
+        match self {
+            FdTarget::Inherited(fd) => {
+                // SAFETY: the standard streams stay open for the lifetime of the shell
+                unsafe { BorrowedFd::borrow_raw(*fd) }.try_clone_to_owned()
+            }
+            FdTarget::File(file) => file.as_fd().try_clone_to_owned(),
+        }
+    }
+}
+
+/// The file descriptors a command runs with. The table starts out with the
+/// standard streams of the shell and is modified by applying the command's
+/// redirections from left to right. Descriptors missing from it are closed.
+#[derive(Debug, Clone)]
+pub struct RedirectionInfo {
+    fds: BTreeMap<RawFd, FdTarget>,
 }
 
 impl RedirectionInfo {
     pub fn new() -> RedirectionInfo {
-        RedirectionInfo{
-            stdout: None,
-            stderr: None,
+        RedirectionInfo {
+            fds: (0..3).map(|fd| (fd, FdTarget::Inherited(fd))).collect(),
         }
     }
 
-    pub fn redirect_stdout(&mut self, file_path: String, open_mode: FileOpenMode) {
-        self.stdout = Some((file_path, open_mode));
+    /// Opens `file_path`, relative to `dir`, as `fd`.
+    pub fn redirect(&mut self, fd: RawFd, file_path: &str, dir: &Path, open_mode: FileOpenMode) -> Result<()> {
+        let file = open_file(&dir.join(file_path), open_mode)
+            .map_err(|err| anyhow!("{}: {}", file_path, err))?;
+        self.set(fd, file);
+        Ok(())
     }
 
-    pub fn redirect_stderr(&mut self, file_path: String, open_mode: FileOpenMode) {
-        self.stderr = Some((file_path, open_mode));
+    pub fn set(&mut self, fd: RawFd, file: File) {
+        self.fds.insert(fd, FdTarget::File(Arc::new(file)));
     }
 
-    pub fn get_output(&self) -> Box<dyn Output> {
-        let ret: Box<dyn Output> = match &self.stdout {
-            Some((path, open_mode)) =>
-                Box::new(FileOutput::new(path.clone(), open_mode.clone())),
-            None => Box::new(StdOutput{})
-        };
+    /// Makes `fd` read the given content, e.g. of a here-document.
+    pub fn feed(&mut self, fd: RawFd, content: String) -> Result<()> {
+        let (reader, mut writer) = pipe()?;
+        // Write from a separate thread, so that content exceeding the pipe
+        // capacity cannot block the shell
+        thread::spawn(move || {
+            let _ = writer.write_all(content.as_bytes());
+        });
+        self.set(fd, reader);
+        Ok(())
+    }
+
+    /// Makes `fd` refer to whatever `source_fd` currently refers to.
+    pub fn duplicate(&mut self, fd: RawFd, source_fd: RawFd) -> Result<()> {
+        let target = self
+            .fds
+            .get(&source_fd)
+            .cloned()
+            .ok_or_else(|| anyhow!("{}: Bad file descriptor", source_fd))?;
+        self.fds.insert(fd, target);
+        Ok(())
+    }
 
-        ret
+    pub fn close(&mut self, fd: RawFd) {
+        self.fds.remove(&fd);
+    }
+
+    /// Returns the file stdin refers to, or `None` if it is closed.
+    pub fn get_input(&self) -> Option<File> {
+        let owned_fd = self.fds.get(&0)?.try_clone_owned().ok()?;
+        Some(File::from(owned_fd))
+    }
+
+    pub fn get_output(&self) -> Box<dyn Output> {
+        self.get_output_for(1)
     }
 
     pub fn get_error_output(&self) -> Box<dyn Output> {
-        let ret: Box<dyn Output> = match &self.stderr {
-            Some((path, open_mode)) =>
-                Box::new(FileOutput::new(path.clone(), open_mode.clone())),
-            None => Box::new(StdErrorOutput{})
-        };
+        self.get_output_for(2)
+    }
 
-        ret
-    }}
+    fn get_output_for(&self, fd: RawFd) -> Box<dyn Output> {
+        match self.fds.get(&fd) {
+            Some(FdTarget::Inherited(1)) => Box::new(StdOutput {}),
+            Some(FdTarget::Inherited(2)) => Box::new(StdErrorOutput {}),
+            Some(target) => match target.try_clone_owned() {
+                Ok(owned_fd) => Box::new(FileOutput::new(File::from(owned_fd))),
+                Err(_) => Box::new(ClosedOutput {}),
+            },
+            None => Box::new(ClosedOutput {}),
+        }
+    }
 
-pub trait Output: Debug {
-    fn open(&mut self) -> Result<()>;
+    /// Sets up the standard streams and all further descriptors of the table
+    /// for a child process.
+    pub fn apply_to(&self, cmd: &mut Command) -> Result<()> {
+        let mut closed = vec![];
+        for fd in 0..3 {
+            let stdio = match self.fds.get(&fd) {
+                Some(FdTarget::Inherited(inherited)) if *inherited == fd => Stdio::inherit(),
+                Some(target) => Stdio::from(target.try_clone_owned()?),
+                None => {
+                    closed.push(fd);
+                    Stdio::null()
+                }
+            };
+            match fd {
+                0 => cmd.stdin(stdio),
+                1 => cmd.stdout(stdio),
+                _ => cmd.stderr(stdio),
+            };
+        }
+
+        let mut sources = vec![];
+        let mut mappings = vec![];
+        for (fd, target) in self.fds.range(3..) {
+            let owned_fd = target.try_clone_owned()?;
+            mappings.push((owned_fd.as_raw_fd(), *fd));
+            sources.push(owned_fd);
+        }
+        if mappings.is_empty() && closed.is_empty() {
+            return Ok(());
+        }
+        let min_free_fd = mappings.iter().map(|(_, fd)| fd + 1).max().unwrap_or(3);
 
-    fn print(&mut self, text: &str);
+        // SAFETY: only async-signal-safe functions are called between fork and exec
+        unsafe {
+            cmd.pre_exec(move || {
+                let _ = &sources;
+                // Move all sources above the target descriptors first, so that
+                // no source gets overwritten by an earlier dup2.
+                for (source, _) in mappings.iter_mut() {
+                    let moved = libc::fcntl(*source, libc::F_DUPFD_CLOEXEC, min_free_fd);
+                    if moved < 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                    *source = moved;
+                }
+                for (source, fd) in mappings.iter() {
+                    if libc::dup2(*source, *fd) < 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+                for fd in closed.iter() {
+                    libc::close(*fd);
+                }
+                Ok(())
+            });
+        }
+
+        Ok(())
+    }
+}
+
+fn open_file(file_path: &Path, open_mode: FileOpenMode) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    match open_mode {
+        FileOpenMode::Create => options.write(true).create(true).truncate(true),
+        FileOpenMode::Append => options.create(true).append(true),
+        FileOpenMode::Read => options.read(true),
+        FileOpenMode::ReadWrite => options.read(true).write(true).create(true),
+    };
+    options.open(file_path)
+}
+
+/// Creates a pipe and returns its read and write end.
+pub fn pipe() -> Result<(File, File)> {
+    let mut fds = [0; 2];
+    // SAFETY: pipe2 initializes both descriptors on success
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } < 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    // SAFETY: the descriptors are open and exclusively owned from here on
+    let (reader, writer) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+    Ok((reader, writer))
+}
 
-    fn println(&mut self, text: &str) {
-        self.print(&format!("{text}\n"));
+pub trait Output: Debug {
+    /// Writes `text`. Fails e.g. with `BrokenPipe` if the reading end of a
+    /// pipe has been closed.
+    fn print(&mut self, text: &str) -> io::Result<()>;
+
+    fn println(&mut self, text: &str) -> io::Result<()> {
+        self.print(&format!("{text}\n"))
     }
 
     fn close(&mut self);
@@ -67,27 +219,21 @@ pub trait Output: Debug {
 struct StdOutput {}
 
 impl Output for StdOutput {
-    fn open(&mut self) -> Result<()> {
-        Ok(())
+    fn print(&mut self, text: &str) -> io::Result<()> {
+        io::stdout().write_all(text.as_bytes())
     }
 
-    fn print(&mut self, text: &str) {
-        print!("{}", text);
+    fn close(&mut self) {
+        let _ = io::stdout().flush();
     }
-
-    fn close(&mut self) {}
 }
 
 #[derive(Debug)]
 struct StdErrorOutput {}
 
 impl Output for StdErrorOutput {
-    fn open(&mut self) -> Result<()> {
-        Ok(())
-    }
-
-    fn print(&mut self, text: &str) {
-        eprint!("{}", text);
+    fn print(&mut self, text: &str) -> io::Result<()> {
+        io::stderr().write_all(text.as_bytes())
     }
 
     fn close(&mut self) {}
@@ -95,41 +241,22 @@ impl Output for StdErrorOutput {
 
 #[derive(Debug)]
 struct FileOutput {
-    file_path: String,
-    open_mode: FileOpenMode,
     file: Option<File>,
 }
 
 impl FileOutput {
-    pub fn new(file_path: String, open_mode: FileOpenMode) -> FileOutput {
-        FileOutput{
-            file_path,
-            open_mode,
-            file: None,
+    pub fn new(file: File) -> FileOutput {
+        FileOutput {
+            file: Some(file),
         }
     }
 }
 
 impl Output for FileOutput {
-    fn open(&mut self) -> Result<()> {
-        let mut options = OpenOptions::new();
-        self.file = match &self.open_mode {
-            FileOpenMode::Create => {
-                Some(File::create(self.file_path.as_str())?)
-            }
-            FileOpenMode::Append => {
-                Some(options
-                    .create(true)
-                    .append(true)
-                    .open(self.file_path.as_str())?)
-            }
-        };
-        Ok(())
-    }
-
-    fn print(&mut self, text: &str) {
-        if let Some(ref mut file) = self.file {
-            write!(file, "{text}").expect("Cannot write to file");
+    fn print(&mut self, text: &str) -> io::Result<()> {
+        match self.file {
+            Some(ref mut file) => file.write_all(text.as_bytes()),
+            None => Ok(()),
         }
     }
 
@@ -137,3 +264,43 @@ impl Output for FileOutput {
         self.file = None;
     }
 }
+
+/// Output to a closed file descriptor. Everything written to it is discarded.
+#[derive(Debug)]
+struct ClosedOutput {}
+
+impl Output for ClosedOutput {
+    fn print(&mut self, _text: &str) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn close(&mut self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_duplicate_and_close() {
+        let (mut reader, writer) = pipe().unwrap();
+        let mut io = RedirectionInfo::new();
+        io.set(3, writer);
+        io.duplicate(1, 3).unwrap();
+        io.close(3);
+        let Some(FdTarget::File(file)) = io.fds.get(&1) else {
+            panic!("file expected for descriptor 1");
+        };
+        (&**file).write_all(b"content").unwrap();
+        drop(io);
+        let mut content = String::new();
+        reader.read_to_string(&mut content).unwrap();
+        assert_eq!(content, "content");
+
+        let mut io = RedirectionInfo::new();
+        assert!(io.duplicate(1, 3).is_err());
+        io.close(0);
+        assert!(!io.fds.contains_key(&0));
+    }
+}
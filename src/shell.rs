@@ -0,0 +1,416 @@
+use crate::arith;
+use crate::ast::FunctionDefinition;
+use crate::history::History;
+use anyhow::{anyhow, Result};
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// State of a running shell. Subshells like the stages of a pipeline work on
+/// a clone of it.
+#[derive(Clone)]
+pub struct Shell {
+    pub history: History,
+    /// Shell variables, sorted by name. The environment of the shell is
+    /// imported as exported variables.
+    vars: BTreeMap<String, Variable>,
+    /// Exit status of the most recently executed pipeline.
+    pub last_status: i32,
+    /// Positional parameters `$1`, `$2`, ...
+    pub positional: Vec<String>,
+    /// Options that are changed with `shopt`.
+    options: BTreeMap<&'static str, bool>,
+    /// Number of loops around the command being executed.
+    pub loop_depth: usize,
+    /// Functions by name.
+    functions: HashMap<String, Arc<FunctionDefinition>>,
+    /// Active function calls, innermost last.
+    frames: Vec<Frame>,
+    /// Aliases defined with `alias`, sorted by name.
+    pub aliases: BTreeMap<String, String>,
+    /// Working directory. `cd` only changes it here, not for the process, so
+    /// that subshells can change theirs independently. Relative paths are
+    /// resolved against it.
+    pub cwd: PathBuf,
+}
+
+/// State of a caller that is restored when the called function returns.
+#[derive(Clone)]
+struct Frame {
+    positional: Vec<String>,
+    loop_depth: usize,
+    /// Variables that the local variables of the function shadow, `None`
+    /// for unset variables.
+    shadowed: HashMap<String, Option<Variable>>,
+}
+
+/// A shell variable with its attributes.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Variable {
+    /// `None` for variables that were declared without assigning a value.
+    pub value: Option<String>,
+    /// `-i`: assigned values are evaluated as arithmetic expressions.
+    pub integer: bool,
+    /// `-l`: assigned values are converted to lowercase.
+    pub lowercase: bool,
+    /// `-r`: the variable can neither be assigned to nor unset.
+    pub readonly: bool,
+    /// `-u`: assigned values are converted to uppercase.
+    pub uppercase: bool,
+    /// `-x`: the variable is passed to the environment of commands.
+    pub exported: bool,
+}
+
+impl Variable {
+    /// Letters of the attributes in the order `declare -p` prints them.
+    pub const FLAGS: [char; 5] = ['i', 'l', 'r', 'u', 'x'];
+
+    fn flag_mut(&mut self, flag: char) -> Option<&mut bool> {
+        match flag {
+            'i' => Some(&mut self.integer),
+            'l' => Some(&mut self.lowercase),
+            'r' => Some(&mut self.readonly),
+            'u' => Some(&mut self.uppercase),
+            'x' => Some(&mut self.exported),
+            _ => None,
+        }
+    }
+
+    pub fn has_flag(&self, flag: char) -> bool {
+        match flag {
+            'i' => self.integer,
+            'l' => self.lowercase,
+            'r' => self.readonly,
+            'u' => self.uppercase,
+            'x' => self.exported,
+            _ => false,
+        }
+    }
+
+    /// Returns the letters of all attributes that are set, e.g. `"rx"`.
+    pub fn flags(&self) -> String {
+        Self::FLAGS
+            .into_iter()
+            .filter(|flag| self.has_flag(*flag))
+            .collect()
+    }
+}
+
+/// Options known to `shopt` and whether they are enabled by default.
+const OPTIONS: [(&str, bool); 7] = [
+    ("dotglob", false),
+    ("extglob", false),
+    ("failglob", false),
+    ("globstar", false),
+    ("interactive_comments", true),
+    ("nocaseglob", false),
+    ("nullglob", false),
+];
+
+impl Shell {
+    pub fn new() -> Self {
+        Self {
+            history: History::new(),
+            vars: env::vars_os()
+                .filter_map(|(name, value)| {
+                    Some((name.into_string().ok()?, value.into_string().ok()?))
+                })
+                .filter(|(name, _)| is_valid_name(name))
+                .map(|(name, value)| {
+                    let variable = Variable {
+                        value: Some(value),
+                        exported: true,
+                        ..Variable::default()
+                    };
+                    (name, variable)
+                })
+                .collect(),
+            last_status: 0,
+            positional: vec![],
+            options: OPTIONS.into_iter().collect(),
+            loop_depth: 0,
+            functions: HashMap::new(),
+            frames: vec![],
+            aliases: BTreeMap::new(),
+            cwd: env::current_dir().unwrap_or_else(|_| PathBuf::from("/")),
+        }
+    }
+
+    /// Returns `path` relative to the working directory of the shell.
+    pub fn resolve_path(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.cwd.join(path)
+    }
+
+    /// Returns the value of a variable, positional or special parameter, or
+    /// `None` if it is unset.
+    pub fn get_parameter(&self, name: &str) -> Option<String> {
+        match name {
+            "?" => Some(self.last_status.to_string()),
+            "$" => Some(std::process::id().to_string()),
+            "#" => Some(self.positional.len().to_string()),
+            "@" => Some(self.positional.join(" ")),
+            // Joined with the first character of IFS, a space if it is unset
+            "*" => {
+                let separator = match self.vars.get("IFS").and_then(|var| var.value.as_deref()) {
+                    Some(ifs) => ifs.chars().next().map(String::from).unwrap_or_default(),
+                    None => " ".to_string(),
+                };
+                Some(self.positional.join(&separator))
+            }
+            "0" => Some(env::args().next().unwrap_or_else(|| "shell".to_string())),
+            "-" => Some(String::new()),
+            "!" => None,
+            _ if name.starts_with(|ch: char| ch.is_ascii_digit()) => {
+                let idx = name.parse::<usize>().ok()?;
+                self.positional.get(idx.checked_sub(1)?).cloned()
+            }
+            _ => self.vars.get(name)?.value.clone(),
+        }
+    }
+
+    pub fn variable(&self, name: &str) -> Option<&Variable> {
+        self.vars.get(name)
+    }
+
+    /// Returns all variables including unset declared ones, sorted by name.
+    pub fn variables(&self) -> impl Iterator<Item = (&str, &Variable)> {
+        self.vars
+            .iter()
+            .map(|(name, variable)| (name.as_str(), variable))
+    }
+
+    /// Returns the environment of executed commands.
+    pub fn exported_vars(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.vars
+            .iter()
+            .filter(|(_, variable)| variable.exported)
+            .filter_map(|(name, variable)| {
+                variable
+                    .value
+                    .as_deref()
+                    .map(|value| (name.as_str(), value))
+            })
+    }
+
+    /// Assigns `value` to a variable after converting it according to the
+    /// variable's attributes.
+    pub fn set_var(&mut self, name: &str, value: &str) -> Result<()> {
+        let variable = self.vars.get(name).cloned().unwrap_or_default();
+        if variable.readonly {
+            return Err(anyhow!("{name}: readonly variable"));
+        }
+        let value = if variable.integer {
+            arith::evaluate(value, self)?.to_string()
+        } else if variable.lowercase {
+            value.to_lowercase()
+        } else if variable.uppercase {
+            value.to_uppercase()
+        } else {
+            value.to_string()
+        };
+        self.vars.entry(name.to_string()).or_default().value = Some(value);
+        Ok(())
+    }
+
+    /// Declares a variable without a value unless it already exists.
+    pub fn declare_var(&mut self, name: &str) {
+        self.vars.entry(name.to_string()).or_default();
+    }
+
+    /// Sets or clears the attribute with the letter `flag`, declaring the
+    /// variable if necessary. `-l` and `-u` exclude each other.
+    pub fn set_attribute(&mut self, name: &str, flag: char, enabled: bool) -> Result<()> {
+        let variable = self.vars.entry(name.to_string()).or_default();
+        if variable.readonly && flag == 'r' && !enabled {
+            return Err(anyhow!("{name}: readonly variable"));
+        }
+        match (flag, enabled) {
+            ('l', true) => variable.uppercase = false,
+            ('u', true) => variable.lowercase = false,
+            _ => {}
+        }
+        let attribute = variable
+            .flag_mut(flag)
+            .ok_or_else(|| anyhow!("-{flag}: invalid option"))?;
+        *attribute = enabled;
+        Ok(())
+    }
+
+    /// Replaces a variable regardless of its attributes, e.g. to restore it
+    /// after a temporary assignment. `None` unsets it.
+    pub fn restore_var(&mut self, name: &str, variable: Option<Variable>) {
+        match variable {
+            Some(variable) => self.vars.insert(name.to_string(), variable),
+            None => self.vars.remove(name),
+        };
+    }
+
+    pub fn unset_var(&mut self, name: &str) -> Result<()> {
+        if self
+            .vars
+            .get(name)
+            .is_some_and(|variable| variable.readonly)
+        {
+            return Err(anyhow!("{name}: cannot unset: readonly variable"));
+        }
+        self.vars.remove(name);
+        Ok(())
+    }
+
+    pub fn function(&self, name: &str) -> Option<Arc<FunctionDefinition>> {
+        self.functions.get(name).cloned()
+    }
+
+    pub fn define_function(&mut self, function: FunctionDefinition) {
+        self.functions
+            .insert(function.name.clone(), Arc::new(function));
+    }
+
+    /// Removes a function and returns whether it was defined.
+    pub fn unset_function(&mut self, name: &str) -> bool {
+        self.functions.remove(name).is_some()
+    }
+
+    /// Enters a function call with `args` as positional parameters. Loops
+    /// of the caller cannot be left with `break` from inside the function.
+    pub fn push_frame(&mut self, args: Vec<String>) {
+        self.frames.push(Frame {
+            positional: std::mem::replace(&mut self.positional, args),
+            loop_depth: std::mem::take(&mut self.loop_depth),
+            shadowed: HashMap::new(),
+        });
+    }
+
+    /// Leaves the innermost function call and restores the state of its
+    /// caller.
+    pub fn pop_frame(&mut self) {
+        let Some(frame) = self.frames.pop() else {
+            return;
+        };
+        self.positional = frame.positional;
+        self.loop_depth = frame.loop_depth;
+        for (name, variable) in frame.shadowed {
+            self.restore_var(&name, variable);
+        }
+    }
+
+    pub fn in_function(&self) -> bool {
+        !self.frames.is_empty()
+    }
+
+    /// Makes a variable local to the innermost function call. It is unset
+    /// until a value is assigned.
+    pub fn make_local(&mut self, name: &str) -> Result<()> {
+        if self
+            .vars
+            .get(name)
+            .is_some_and(|variable| variable.readonly)
+        {
+            return Err(anyhow!("{name}: readonly variable"));
+        }
+        let frame = self
+            .frames
+            .last_mut()
+            .ok_or_else(|| anyhow!("can only be used in a function"))?;
+        if !frame.shadowed.contains_key(name) {
+            let shadowed = self.vars.remove(name);
+            // Like in bash, only the export attribute is inherited.
+            if shadowed.as_ref().is_some_and(|variable| variable.exported) {
+                let variable = Variable {
+                    exported: true,
+                    ..Variable::default()
+                };
+                self.vars.insert(name.to_string(), variable);
+            }
+            frame.shadowed.insert(name.to_string(), shadowed);
+        }
+        Ok(())
+    }
+
+    pub fn is_option_set(&self, name: &str) -> bool {
+        self.options.get(name).copied().unwrap_or(false)
+    }
+
+    pub fn set_option(&mut self, name: &str, enabled: bool) -> Result<()> {
+        match self.options.get_mut(name) {
+            Some(option) => {
+                *option = enabled;
+                Ok(())
+            }
+            None => Err(anyhow!("shopt: {name}: invalid shell option name")),
+        }
+    }
+
+    /// Returns all options with their current state, sorted by name.
+    pub fn options(&self) -> impl Iterator<Item = (&'static str, bool)> + '_ {
+        self.options.iter().map(|(name, enabled)| (*name, *enabled))
+    }
+}
+
+/// Returns whether `name` can be assigned to, i.e. is no positional or
+/// special parameter.
+pub fn is_valid_name(name: &str) -> bool {
+    name.starts_with(|ch: char| ch.is_ascii_alphabetic() || ch == '_')
+        && name
+            .chars()
+            .all(|ch| ch.is_ascii_alphanumeric() || ch == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_attributes_convert_assigned_values() {
+        let mut shell = Shell::new();
+        shell.set_attribute("n", 'i', true).unwrap();
+        shell.set_var("n", "2 * 3").unwrap();
+        shell.set_attribute("s", 'u', true).unwrap();
+        shell.set_attribute("s", 'l', true).unwrap();
+        shell.set_var("s", "MiXed").unwrap();
+        assert_eq!(shell.get_parameter("n").as_deref(), Some("6"));
+        assert_eq!(shell.get_parameter("s").as_deref(), Some("mixed"));
+        assert_eq!(shell.variable("s").unwrap().flags(), "l");
+    }
+
+    #[test]
+    fn test_readonly_variables() {
+        let mut shell = Shell::new();
+        shell.set_var("r", "1").unwrap();
+        shell.set_attribute("r", 'r', true).unwrap();
+        assert_eq!(shell.set_var("r", "2").unwrap_err().to_string(), "r: readonly variable");
+        assert!(shell.unset_var("r").is_err());
+        assert!(shell.set_attribute("r", 'r', false).is_err());
+        assert_eq!(shell.get_parameter("r").as_deref(), Some("1"));
+    }
+
+    #[test]
+    fn test_local_variables_are_restored() {
+        let mut shell = Shell::new();
+        shell.set_var("x", "outer").unwrap();
+        shell.set_attribute("x", 'x', true).unwrap();
+        shell.push_frame(vec!["arg".to_string()]);
+        shell.make_local("x").unwrap();
+        assert_eq!(shell.get_parameter("x"), None);
+        assert!(shell.variable("x").unwrap().exported);
+        shell.set_var("x", "inner").unwrap();
+        assert_eq!(shell.get_parameter("1").as_deref(), Some("arg"));
+        shell.pop_frame();
+        assert_eq!(shell.get_parameter("x").as_deref(), Some("outer"));
+        assert!(shell.make_local("x").is_err());
+    }
+
+    #[test]
+    fn test_exported_vars() {
+        let mut shell = Shell::new();
+        shell.set_var("exported_var", "1").unwrap();
+        shell.set_attribute("exported_var", 'x', true).unwrap();
+        shell.set_var("shell_var", "2").unwrap();
+        shell.declare_var("declared_var");
+        shell.set_attribute("declared_var", 'x', true).unwrap();
+        let exported: Vec<_> = shell.exported_vars().collect();
+        assert!(exported.contains(&("exported_var", "1")));
+        assert!(!exported.iter().any(|(name, _)| *name == "shell_var" || *name == "declared_var"));
+    }
+}
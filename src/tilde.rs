@@ -0,0 +1,153 @@
+use crate::ast::{Word, WordPart};
+use crate::shell::Shell;
+use std::ffi::{CStr, CString};
+
+/// Replaces a tilde-prefix at the start of the word with the directory it
+/// stands for. In assignments like `PATH=~/bin:~user/bin` tilde-prefixes
+/// after the `=` and after each `:` are replaced as well. A prefix has to
+/// be unquoted and is left as it is if it names no directory.
+pub fn expand_tilde(word: &Word, shell: &Shell) -> Word {
+    let is_assignment = word.assignment_name().is_some();
+    let is_prefix_end = |ch: char| ch == '/' || (is_assignment && ch == ':');
+
+    let mut parts = vec![];
+    for (idx, part) in word.parts.iter().enumerate() {
+        let WordPart::Literal(text) = part else {
+            parts.push(part.clone());
+            continue;
+        };
+        let is_last = idx == word.parts.len() - 1;
+        let mut literal = String::new();
+        let mut rest = text.as_str();
+        let mut at_start = idx == 0;
+        if idx == 0 && is_assignment {
+            let value_start = text.find('=').unwrap_or(0) + 1;
+            literal.push_str(&text[..value_start]);
+            rest = &text[value_start..];
+        }
+
+        loop {
+            if at_start && rest.starts_with('~') {
+                let prefix_end = match rest.find(is_prefix_end) {
+                    Some(end) => Some(end),
+                    None if is_last => Some(rest.len()),
+                    None => None,
+                };
+                let expanded =
+                    prefix_end.and_then(|end| Some((tilde_dir(&rest[1..end], shell)?, end)));
+                if let Some((dir, end)) = expanded {
+                    if !literal.is_empty() {
+                        parts.push(WordPart::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(WordPart::SingleQuoted(dir));
+                    rest = &rest[end..];
+                }
+            }
+            match rest.find(':').filter(|_| is_assignment) {
+                Some(colon) => {
+                    literal.push_str(&rest[..=colon]);
+                    rest = &rest[colon + 1..];
+                    at_start = true;
+                }
+                None => {
+                    literal.push_str(rest);
+                    break;
+                }
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(WordPart::Literal(literal));
+        }
+    }
+
+    Word {
+        parts,
+        span: word.span,
+    }
+}
+
+/// Returns the directory for the tilde-prefix `~name`: the home directory
+/// for an empty name or a user name, `$PWD` for `+` and `$OLDPWD` for `-`.
+fn tilde_dir(name: &str, shell: &Shell) -> Option<String> {
+    match name {
+        "" => shell.get_parameter("HOME"),
+        "+" => shell
+            .get_parameter("PWD")
+            .or_else(|| Some(shell.cwd.display().to_string())),
+        "-" => shell.get_parameter("OLDPWD"),
+        _ => home_dir_of(name),
+    }
+}
+
+/// Looks up the home directory of a user in the passwd database.
+fn home_dir_of(user: &str) -> Option<String> {
+    let name = CString::new(user).ok()?;
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buffer = vec![0 as libc::c_char; 4096];
+    let mut result = std::ptr::null_mut();
+    let rc = unsafe {
+        libc::getpwnam_r(
+            name.as_ptr(),
+            &mut passwd,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+    if rc != 0 || result.is_null() || passwd.pw_dir.is_null() {
+        return None;
+    }
+    let dir = unsafe { CStr::from_ptr(passwd.pw_dir) };
+    Some(dir.to_string_lossy().into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Span;
+
+    fn expand(parts: Vec<WordPart>, shell: &Shell) -> String {
+        let word = Word {
+            parts,
+            span: Span::default(),
+        };
+        expand_tilde(&word, shell).unquoted()
+    }
+
+    fn expand_literal(text: &str, shell: &Shell) -> String {
+        expand(vec![WordPart::Literal(text.to_string())], shell)
+    }
+
+    #[test]
+    fn test_expand_tilde() {
+        let mut shell = Shell::new();
+        shell.set_var("HOME", "/home/me").unwrap();
+        shell.set_var("PWD", "/work").unwrap();
+        shell.set_var("OLDPWD", "/old").unwrap();
+        let root_home = home_dir_of("root").unwrap();
+
+        assert_eq!(expand_literal("~", &shell), "/home/me");
+        assert_eq!(expand_literal("~/src", &shell), "/home/me/src");
+        assert_eq!(expand_literal("~+/x", &shell), "/work/x");
+        assert_eq!(expand_literal("~-", &shell), "/old");
+        assert_eq!(expand_literal("~root/x", &shell), format!("{root_home}/x"));
+        assert_eq!(expand_literal("~no_such_user/x", &shell), "~no_such_user/x");
+        assert_eq!(expand_literal("a~", &shell), "a~");
+        assert_eq!(expand_literal("a/~:~", &shell), "a/~:~");
+        assert_eq!(
+            expand_literal("PATH=~/bin:~-:/usr/~", &shell),
+            "PATH=/home/me/bin:/old:/usr/~"
+        );
+    }
+
+    #[test]
+    fn test_quoted_tilde_prefix() {
+        let shell = Shell::new();
+        let parts = vec![
+            WordPart::Literal("~".to_string()),
+            WordPart::DoubleQuoted(vec![WordPart::Literal("/x".to_string())]),
+        ];
+        assert_eq!(expand(parts, &shell), "~/x");
+        assert_eq!(expand(vec![WordPart::Escaped('~')], &shell), "~");
+    }
+}